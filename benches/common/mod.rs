@@ -0,0 +1,14 @@
+use std::fs;
+
+/// Real puzzle input when it has been downloaded with `--solve`, `None` for the example from the puzzle.
+#[allow(dead_code)]
+pub fn input(day: u8) -> Option<String> {
+    fs::read_to_string(format!("fixtures/day_2023_{:02}.txt", day)).ok()
+}
+
+/// Real puzzle input, for benches whose numbers mean nothing on the example. Panics if it hasn't been
+/// downloaded with `--solve`.
+#[allow(dead_code)]
+pub fn real_input(day: u8) -> String {
+    input(day).unwrap_or_else(|| panic!("no input in fixtures/, download it with `aoc-2023 {} --solve` first", day))
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use aoc_2023::solutions::{solve_for_day, Options};
+
+const EXAMPLE: &str = "???.### 1,1,3
+.??..??...?##. 1,1,3
+?#?#?#?#?#?#?#? 1,3,1,6
+????.#...#... 4,1,1
+????.######..#####. 1,6,5
+?###???????? 3,2,1";
+
+/// The memoised recursion day 12 used before the table DP, as a baseline. It keys its cache on the whole rest
+/// of the row and copies the row whenever it fills in an unknown spring.
+mod memoised {
+    use std::collections::HashMap;
+
+    use itertools::Itertools;
+
+    pub fn solve(data: &str, repeats: usize) -> u64 {
+        data.lines().map(|line| {
+            let (springs, groups) = line.split_once(' ').unwrap();
+            let springs = vec![springs; repeats].join("?").chars().collect::<Vec<_>>();
+            let groups = groups.split(',').map(|g| g.parse::<usize>().unwrap()).collect::<Vec<_>>();
+            count(&mut HashMap::new(), &springs, &groups.repeat(repeats))
+        }).sum()
+    }
+
+    fn count(cache: &mut HashMap<String, u64>, springs: &[char], groups: &[usize]) -> u64 {
+        let key = springs.iter().collect::<String>() + &groups.iter().join(",");
+        if let Some(count) = cache.get(&key) {
+            return *count;
+        }
+        let result = match (springs.first(), groups.first()) {
+            (None, None) => 1,
+            (None, Some(_)) => 0,
+            (Some(_), None) => if springs.contains(&'#') { 0 } else { 1 },
+            (Some('.'), _) => count(cache, &springs[1..], groups),
+            (Some('#'), Some(&length)) => {
+                let fits = springs.len() >= length
+                    && !springs[..length].contains(&'.')
+                    && springs.get(length) != Some(&'#');
+                if fits {
+                    count(cache, &springs[(length + 1).min(springs.len())..], &groups[1..])
+                } else {
+                    0
+                }
+            }
+            _ => ['.', '#'].iter().map(|s| {
+                let mut filled = springs.to_vec();
+                filled[0] = *s;
+                count(cache, &filled, groups)
+            }).sum(),
+        };
+        cache.insert(key, result);
+        result
+    }
+}
+
+fn arrangements(c: &mut Criterion) {
+    let data = common::input(12);
+    let mut group = c.benchmark_group("day12");
+    for fold in [1, 5, 10] {
+        let options = Options { fold: Some(fold), ..Options::default() };
+        group.bench_with_input(BenchmarkId::new("table", fold), &fold, |b, _| {
+            b.iter(|| solve_for_day(12, data.clone(), false, &options).unwrap())
+        });
+    }
+    // the recursion counts in u64, which unfolding 10 times can overflow
+    let text = data.as_deref().unwrap_or(EXAMPLE);
+    for fold in [1, 5] {
+        group.bench_with_input(BenchmarkId::new("memoised", fold), &fold, |b, fold| {
+            b.iter(|| memoised::solve(text, *fold))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, arrangements);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use aoc_2023::solutions::{solve_for_day, Options};
+
+/// Most a real input may take for the billion spin cycles of part 2. A random 100x100 platform, the size of a
+/// real input, took about 100 ms in a release build.
+const TARGET: Duration = Duration::from_millis(200);
+
+/// The billion spin cycles of part 2 on the real input. Fails up front if a single solve misses `TARGET`.
+fn spin_cycles(c: &mut Criterion) {
+    let data = Some(common::real_input(14));
+    let options = Options::default();
+    let started = Instant::now();
+    solve_for_day(14, data.clone(), true, &options).unwrap();
+    let elapsed = started.elapsed();
+    assert!(elapsed < TARGET, "the billion spin cycles took {:?}, more than the {:?} target", elapsed, TARGET);
+
+    c.bench_function("day14/billion_cycles", |b| {
+        b.iter(|| solve_for_day(14, data.clone(), true, &options).unwrap())
+    });
+}
+
+criterion_group!(benches, spin_cycles);
+criterion_main!(benches);
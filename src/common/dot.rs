@@ -0,0 +1,79 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+type Attributes = Vec<(String, String)>;
+
+/// Directed graph in Graphviz DOT form. Attributes are `(key, value)` pairs such as `("color", "red")`.
+#[derive(Debug, Default)]
+pub struct DotGraph {
+    nodes: Vec<(String, Attributes)>,
+    edges: Vec<(String, String, Attributes)>,
+}
+
+impl DotGraph {
+    pub fn new() -> DotGraph {
+        DotGraph::default()
+    }
+
+    pub fn node(&mut self, id: &str, attributes: &[(&str, &str)]) {
+        self.nodes.push((id.to_string(), owned(attributes)));
+    }
+
+    pub fn edge(&mut self, from: &str, to: &str, attributes: &[(&str, &str)]) {
+        self.edges.push((from.to_string(), to.to_string(), owned(attributes)));
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut result = String::from("digraph {\n");
+        for (id, attributes) in &self.nodes {
+            writeln!(result, "    {}{};", quote(id), attribute_list(attributes)).unwrap();
+        }
+        for (from, to, attributes) in &self.edges {
+            writeln!(result, "    {} -> {}{};", quote(from), quote(to), attribute_list(attributes)).unwrap();
+        }
+        result.push_str("}\n");
+        result
+    }
+
+    /// Writes the graph to `path`, reporting the outcome on the terminal.
+    pub fn export(&self, path: &Path) {
+        match fs::write(path, self.to_dot()) {
+            Ok(()) => eprintln!("Graph written to {}", path.display()),
+            Err(e) => eprintln!("Warning: could not write graph to {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn owned(attributes: &[(&str, &str)]) -> Attributes {
+    attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn attribute_list(attributes: &[(String, String)]) -> String {
+    if attributes.is_empty() {
+        return String::new();
+    }
+    let list = attributes.iter().map(|(k, v)| format!("{}={}", k, quote(v))).collect::<Vec<_>>();
+    format!(" [{}]", list.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_dot() {
+        let mut graph = DotGraph::new();
+        graph.node("in", &[("color", "green")]);
+        graph.edge("in", "A", &[("label", "a<2006")]);
+        graph.edge("in", "say \"R\"", &[]);
+        assert_eq!(
+            graph.to_dot(),
+            "digraph {\n    \"in\" [color=\"green\"];\n    \"in\" -> \"A\" [label=\"a<2006\"];\n    \"in\" -> \"say \\\"R\\\"\";\n}\n"
+        );
+    }
+}
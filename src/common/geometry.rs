@@ -0,0 +1,149 @@
+use num_integer::Integer;
+
+pub type Point = (i64, i64);
+
+/// Where a point lies relative to a polygon.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Location {
+    Inside,
+    Boundary,
+    Outside,
+}
+
+/// Simple polygon with integer vertices, given in either winding order.
+///
+/// Consecutive vertices are joined by straight edges and the last vertex is joined back to the first one,
+/// so a closing vertex that repeats the first one is optional.
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    vertices: Vec<Point>,
+}
+
+impl Polygon {
+    pub fn new(mut vertices: Vec<Point>) -> Polygon {
+        if vertices.len() > 1 && vertices.first() == vertices.last() {
+            vertices.pop();
+        }
+        Polygon { vertices }
+    }
+
+    pub fn vertices(&self) -> &[Point] {
+        &self.vertices
+    }
+
+    /// Drops vertices lying on the straight line between their neighbours. The covered area stays the same,
+    /// but queries like `locate` get cheaper for polygons traced cell by cell.
+    pub fn simplified(&self) -> Polygon {
+        let n = self.vertices.len();
+        if n < 3 {
+            return self.clone();
+        }
+        let vertices = (0..n)
+            .filter(|&i| {
+                let previous = self.vertices[(i + n - 1) % n];
+                let next = self.vertices[(i + 1) % n];
+                !on_segment(previous, next, self.vertices[i])
+            })
+            .map(|i| self.vertices[i])
+            .collect();
+        Polygon { vertices }
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (Point, Point)> + '_ {
+        self.vertices.iter().copied().zip(self.vertices.iter().copied().cycle().skip(1))
+    }
+
+    /// Twice the enclosed area, computed with the shoelace formula. Doubling keeps the result integral.
+    pub fn doubled_area(&self) -> i64 {
+        self.edges().map(|(a, b)| det([[a.0, b.0], [a.1, b.1]])).sum::<i64>().abs()
+    }
+
+    pub fn area(&self) -> f64 {
+        self.doubled_area() as f64 / 2.0
+    }
+
+    /// Number of lattice points lying on the edges.
+    pub fn boundary_points(&self) -> i64 {
+        self.edges().map(|(a, b)| (b.0 - a.0).abs().gcd(&(b.1 - a.1).abs())).sum()
+    }
+
+    /// Number of lattice points strictly inside, from Pick's theorem: `A = I + B/2 - 1`.
+    pub fn interior_points(&self) -> i64 {
+        (self.doubled_area() - self.boundary_points() + 2) / 2
+    }
+
+    /// Every lattice point covered by the polygon, edges included.
+    pub fn lattice_points(&self) -> i64 {
+        self.interior_points() + self.boundary_points()
+    }
+
+    pub fn locate(&self, point: Point) -> Location {
+        let mut crossings = 0;
+        for (a, b) in self.edges() {
+            if on_segment(a, b, point) {
+                return Location::Boundary;
+            }
+            // count edges crossing the ray going from the point in the +x direction, half-open in y
+            if (a.1 > point.1) != (b.1 > point.1) {
+                let side = det([[b.0 - a.0, point.0 - a.0], [b.1 - a.1, point.1 - a.1]]);
+                if (side > 0) == (b.1 > a.1) {
+                    crossings += 1;
+                }
+            }
+        }
+        if crossings % 2 == 1 { Location::Inside } else { Location::Outside }
+    }
+}
+
+fn det(m: [[i64; 2]; 2]) -> i64 {
+    m[0][0] * m[1][1] - m[0][1] * m[1][0]
+}
+
+fn on_segment(a: Point, b: Point, p: Point) -> bool {
+    let collinear = det([[b.0 - a.0, p.0 - a.0], [b.1 - a.1, p.1 - a.1]]) == 0;
+    collinear
+        && p.0 >= a.0.min(b.0) && p.0 <= a.0.max(b.0)
+        && p.1 >= a.1.min(b.1) && p.1 <= a.1.max(b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square() {
+        let square = Polygon::new(vec![(0, 0), (0, 4), (4, 4), (4, 0), (0, 0)]);
+        assert_eq!(square.doubled_area(), 32);
+        assert_eq!(square.boundary_points(), 16);
+        assert_eq!(square.interior_points(), 9);
+        assert_eq!(square.lattice_points(), 25);
+    }
+
+    #[test]
+    fn test_triangle() {
+        let triangle = Polygon::new(vec![(0, 0), (4, 0), (0, 3)]);
+        assert_eq!(triangle.area(), 6.0);
+        assert_eq!(triangle.boundary_points(), 8);
+        assert_eq!(triangle.interior_points(), 3);
+    }
+
+    #[test]
+    fn test_locate() {
+        // U-shaped polygon, the notch between x=1..3 above y=2 is outside
+        let u = Polygon::new(vec![(0, 0), (4, 0), (4, 4), (3, 4), (3, 2), (1, 2), (1, 4), (0, 4)]);
+        assert_eq!(u.locate((2, 1)), Location::Inside);
+        assert_eq!(u.locate((2, 3)), Location::Outside);
+        assert_eq!(u.locate((2, 2)), Location::Boundary);
+        assert_eq!(u.locate((4, 3)), Location::Boundary);
+        assert_eq!(u.locate((5, 2)), Location::Outside);
+        assert_eq!(u.locate((-1, 0)), Location::Outside);
+
+        let simplified = Polygon::new(vec![(0, 0), (2, 0), (4, 0), (4, 2), (4, 4), (2, 4), (0, 4), (0, 2)]).simplified();
+        assert_eq!(simplified.vertices(), &[(0, 0), (4, 0), (4, 4), (0, 4)]);
+
+        let inside = (-1..6).flat_map(|x| (-1..6).map(move |y| (x, y)))
+            .filter(|p| u.locate(*p) == Location::Inside)
+            .count() as i64;
+        assert_eq!(inside, u.interior_points());
+    }
+}
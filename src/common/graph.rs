@@ -0,0 +1,86 @@
+/// Strongly connected components of a directed graph given as adjacency lists, found with an iterative
+/// version of Tarjan's algorithm, so deep graphs can't overflow the stack.
+///
+/// Returns the component of every node and the number of components. Components are numbered in reverse
+/// topological order: every edge leads to a component with the same or a smaller number.
+pub fn strongly_connected_components(successors: &[Vec<usize>]) -> (Vec<usize>, usize) {
+    const UNVISITED: usize = usize::MAX;
+    let n = successors.len();
+    let mut index = vec![UNVISITED; n];
+    let mut low_link = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut component = vec![UNVISITED; n];
+    let mut next_index = 0;
+    let mut count = 0;
+
+    for root in 0..n {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // nodes being explored, with the position of the next successor to look at
+        let mut call_stack = vec![(root, 0)];
+        index[root] = next_index;
+        low_link[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some(&(node, next_successor)) = call_stack.last() {
+            if let Some(&successor) = successors[node].get(next_successor) {
+                call_stack.last_mut().unwrap().1 += 1;
+                if index[successor] == UNVISITED {
+                    index[successor] = next_index;
+                    low_link[successor] = next_index;
+                    next_index += 1;
+                    stack.push(successor);
+                    on_stack[successor] = true;
+                    call_stack.push((successor, 0));
+                } else if on_stack[successor] {
+                    low_link[node] = low_link[node].min(index[successor]);
+                }
+                continue;
+            }
+
+            call_stack.pop();
+            if let Some(&(parent, _)) = call_stack.last() {
+                low_link[parent] = low_link[parent].min(low_link[node]);
+            }
+            if low_link[node] == index[node] {
+                loop {
+                    let member = stack.pop().unwrap();
+                    on_stack[member] = false;
+                    component[member] = count;
+                    if member == node {
+                        break;
+                    }
+                }
+                count += 1;
+            }
+        }
+    }
+    (component, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strongly_connected_components() {
+        // 0 -> 1 -> 2 -> 0 is a cycle feeding into 3 <-> 4, and 5 stands alone
+        let successors = vec![vec![1], vec![2], vec![0, 3], vec![4], vec![3], vec![]];
+        let (component, count) = strongly_connected_components(&successors);
+        assert_eq!(count, 3);
+        assert!(component[0] == component[1] && component[1] == component[2]);
+        assert_eq!(component[3], component[4]);
+        assert_ne!(component[0], component[3]);
+        for (node, edges) in successors.iter().enumerate() {
+            assert!(edges.iter().all(|s| component[*s] <= component[node]));
+        }
+
+        // a long chain is fine without recursion
+        let chain = (0..100_000).map(|i| if i + 1 < 100_000 { vec![i + 1] } else { vec![] }).collect::<Vec<_>>();
+        assert_eq!(strongly_connected_components(&chain).1, 100_000);
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::fmt::Formatter;
+use crate::common::interval::{Interval, IntervalSet};
+
+/// Axis-aligned box in any number of dimensions, stored as one `Interval` per axis.
+///
+/// A box is empty as soon as one of its axes is empty.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HyperRect {
+    axes: Vec<Interval>,
+}
+
+impl fmt::Debug for HyperRect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.axes.iter()).finish()
+    }
+}
+
+impl HyperRect {
+    pub fn new(axes: Vec<Interval>) -> HyperRect {
+        HyperRect { axes }
+    }
+
+    pub fn uniform(dimensions: usize, interval: Interval) -> HyperRect {
+        HyperRect { axes: vec![interval; dimensions] }
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.axes.len()
+    }
+
+    pub fn axis(&self, axis: usize) -> Interval {
+        self.axes[axis]
+    }
+
+    pub fn axes(&self) -> &[Interval] {
+        &self.axes
+    }
+
+    pub fn with_axis(&self, axis: usize, interval: Interval) -> HyperRect {
+        let mut result = self.clone();
+        result.axes[axis] = interval;
+        result
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.axes.iter().any(|a| a.is_empty())
+    }
+
+    /// Number of integer points inside the box, or `None` if there are more than a `u128` holds.
+    pub fn degrees_of_freedom(&self) -> Option<u128> {
+        if self.is_empty() {
+            return Some(0);
+        }
+        // widened, as an axis can hold more values than an i64 counts
+        self.axes.iter().try_fold(1u128, |volume, a| volume.checked_mul((a.end() as i128 - a.start() as i128) as u128))
+    }
+
+    pub fn contains(&self, point: &[i64]) -> bool {
+        point.len() == self.axes.len() && self.axes.iter().zip(point).all(|(a, v)| a.contains(*v))
+    }
+
+    /// Splits along `axis` into the part below `point` and the part at or above it.
+    pub fn split_at(&self, axis: usize, point: i64) -> (HyperRect, HyperRect) {
+        let (lower, upper) = self.axes[axis].split_at(point);
+        (self.with_axis(axis, lower), self.with_axis(axis, upper))
+    }
+
+    /// Splits along `axis` into the boxes whose values on that axis satisfy `predicate` and the boxes that don't.
+    /// Empty boxes are dropped from both sides.
+    pub fn split_by(&self, axis: usize, predicate: &IntervalSet) -> (Vec<HyperRect>, Vec<HyperRect>) {
+        let values = IntervalSet::from(self.axes[axis]);
+        let pieces = |set: IntervalSet| -> Vec<HyperRect> {
+            set.iter()
+                .map(|i| self.with_axis(axis, *i))
+                .filter(|r| !r.is_empty())
+                .collect()
+        };
+        (pieces(values.intersection(predicate)), pieces(values.difference(predicate)))
+    }
+
+    pub fn intersection(&self, other: &HyperRect) -> HyperRect {
+        HyperRect {
+            axes: self.axes.iter().zip(other.axes.iter()).map(|(a, b)| a.intersection(b)).collect(),
+        }
+    }
+
+    /// Removes `other` from the box. The remaining volume is returned as disjoint, non-empty boxes.
+    pub fn subtract(&self, other: &HyperRect) -> Vec<HyperRect> {
+        if self.intersection(other).is_empty() {
+            return if self.is_empty() { vec![] } else { vec![self.clone()] };
+        }
+
+        let mut result = Vec::new();
+        let mut rest = self.clone();
+        for (axis, cut) in other.axes.iter().enumerate() {
+            let (below, _) = rest.split_at(axis, cut.start());
+            let (_, above) = rest.split_at(axis, cut.end());
+            result.extend([below, above].into_iter().filter(|r| !r.is_empty()));
+            rest = rest.with_axis(axis, rest.axes[axis].intersection(cut));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use super::*;
+
+    fn rect_strategy() -> impl Strategy<Value = HyperRect> {
+        prop::collection::vec((-10i64..10, 0i64..8), 3)
+            .prop_map(|axes| HyperRect::new(axes.into_iter().map(|(s, l)| Interval::with_length(s, l)).collect()))
+    }
+
+    #[test]
+    fn test_split_by() {
+        let rect = HyperRect::uniform(2, Interval::inclusive(1, 10));
+        let predicate = IntervalSet::from_iter([Interval::inclusive(1, 2), Interval::inclusive(5, 6)]);
+        let (passing, failing) = rect.split_by(1, &predicate);
+        assert_eq!(passing.iter().map(|r| r.degrees_of_freedom().unwrap()).sum::<u128>(), 40);
+        assert_eq!(failing.iter().map(|r| r.degrees_of_freedom().unwrap()).sum::<u128>(), 60);
+        assert!(passing.iter().all(|r| r.axis(0) == Interval::inclusive(1, 10)));
+
+        let huge = HyperRect::uniform(3, Interval::new(i64::MIN, i64::MAX));
+        assert_eq!(huge.with_axis(2, Interval::inclusive(0, 0)).degrees_of_freedom(), Some((u64::MAX as u128) * (u64::MAX as u128)));
+        assert_eq!(huge.degrees_of_freedom(), None);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_subtract_volume(a in rect_strategy(), b in rect_strategy()) {
+            let pieces = a.subtract(&b);
+            let volume = pieces.iter().map(|p| p.degrees_of_freedom().unwrap()).sum::<u128>();
+            prop_assert_eq!(volume, a.degrees_of_freedom().unwrap() - a.intersection(&b).degrees_of_freedom().unwrap());
+            prop_assert!(pieces.iter().all(|p| p.intersection(&b).is_empty()));
+            for (i, p) in pieces.iter().enumerate() {
+                prop_assert!(pieces[i + 1..].iter().all(|q| p.intersection(q).is_empty()));
+            }
+        }
+
+        #[test]
+        fn prop_split_at_preserves_volume(a in rect_strategy(), axis in 0usize..3, point in -12i64..20) {
+            let (lower, upper) = a.split_at(axis, point);
+            let volume = lower.degrees_of_freedom().unwrap() + upper.degrees_of_freedom().unwrap();
+            prop_assert_eq!(volume, a.degrees_of_freedom().unwrap());
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::common::render::Render;
+
+/// Side length, in pixels, of the square drawn for every grid cell.
+const CELL_SIZE: usize = 4;
+
+/// RGB raster of a rendered grid, three bytes per pixel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Draws every glyph of `state` as a `cell_size` square in the glyph's colour.
+    pub fn from_render(state: &impl Render, cell_size: usize) -> Image {
+        let glyphs = state.glyphs();
+        let columns = glyphs.iter().map(|row| row.len()).max().unwrap_or(0);
+        let width = columns * cell_size;
+        let height = glyphs.len() * cell_size;
+        let mut pixels = vec![0; width * height * 3];
+        for (y, row) in glyphs.iter().enumerate() {
+            for (x, glyph) in row.iter().enumerate() {
+                let rgb = glyph.color.rgb();
+                for py in y * cell_size..(y + 1) * cell_size {
+                    for px in x * cell_size..(x + 1) * cell_size {
+                        let offset = (py * width + px) * 3;
+                        pixels[offset..offset + 3].copy_from_slice(&rgb);
+                    }
+                }
+            }
+        }
+        Image { width, height, pixels }
+    }
+
+    pub fn write_ppm(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.pixels)
+    }
+
+    pub fn write_png(&self, writer: impl Write) -> Result<(), Box<dyn Error>> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+/// Writes animation frames as an endlessly looping GIF. Every frame has to have the size of the first one.
+pub fn write_gif(frames: &[Image], writer: impl Write) -> Result<(), Box<dyn Error>> {
+    let first = frames.first().ok_or("no frames to write")?;
+    let mut encoder = gif::Encoder::new(writer, first.width as u16, first.height as u16, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for image in frames {
+        let mut frame = gif::Frame::from_rgb_speed(image.width as u16, image.height as u16, &image.pixels, 10);
+        frame.delay = 10;
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
+
+/// Settings of the `--export-image` flag. The file extension picks the format: `.png`, `.ppm` or `.gif`.
+///
+/// Only GIF exports keep the intermediate frames, the other formats contain just the final state.
+#[derive(Debug)]
+pub struct ImageExporter {
+    path: PathBuf,
+    frames: RefCell<Vec<Image>>,
+}
+
+impl ImageExporter {
+    pub fn new(path: impl Into<PathBuf>) -> ImageExporter {
+        ImageExporter { path: path.into(), frames: RefCell::new(Vec::new()) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn extension(&self) -> String {
+        self.path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase()
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.extension() == "gif"
+    }
+
+    /// Records an intermediate state. Does nothing unless exporting an animation.
+    pub fn frame(&self, state: &impl Render) {
+        if self.is_animated() {
+            self.frames.borrow_mut().push(Image::from_render(state, CELL_SIZE));
+        }
+    }
+
+    /// Writes the final state, preceded by the recorded frames for animations.
+    pub fn save(&self, state: &impl Render) -> Result<(), Box<dyn Error>> {
+        let extension = self.extension();
+        if !["ppm", "png", "gif"].contains(&extension.as_str()) {
+            return Err(format!("unsupported image format {:?}, use png, ppm or gif", extension).into());
+        }
+        let image = Image::from_render(state, CELL_SIZE);
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        match extension.as_str() {
+            "ppm" => image.write_ppm(&mut writer)?,
+            "png" => image.write_png(&mut writer)?,
+            "gif" => {
+                let mut frames = self.frames.borrow_mut();
+                frames.push(image);
+                write_gif(&frames, &mut writer)?;
+            }
+            _ => unreachable!(),
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Like `save`, but reports the outcome on the terminal instead of returning it.
+    pub fn export(&self, state: &impl Render) {
+        match self.save(state) {
+            Ok(()) => eprintln!("Image written to {}", self.path.display()),
+            Err(e) => eprintln!("Warning: could not export image to {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::render::{Color, Glyph};
+    use super::*;
+
+    struct Pair;
+
+    impl Render for Pair {
+        fn glyphs(&self) -> Vec<Vec<Glyph>> {
+            vec![vec![Glyph::new('#', Color::Rgb(1, 2, 3)), Glyph::new('.', Color::Rgb(4, 5, 6))]]
+        }
+    }
+
+    #[test]
+    fn test_from_render() {
+        let image = Image::from_render(&Pair, 2);
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(&image.pixels[..12], &[1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6]);
+        assert_eq!(&image.pixels[..12], &image.pixels[12..]);
+
+        let mut ppm = Vec::new();
+        Image::from_render(&Pair, 1).write_ppm(&mut ppm).unwrap();
+        assert_eq!(ppm, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+    }
+}
@@ -0,0 +1,306 @@
+use std::cmp;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Half-open integer interval `[start, end)`.
+///
+/// Every empty interval is normalised to the same value, so two empty intervals always compare equal.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Interval {
+    start: i64,
+    end: i64,
+}
+
+impl fmt::Debug for Interval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            write!(f, "[)")
+        } else {
+            write!(f, "[{}, {})", self.start, self.end)
+        }
+    }
+}
+
+impl Interval {
+    pub fn new(start: i64, end: i64) -> Interval {
+        if start >= end {
+            Interval::empty()
+        } else {
+            Interval { start, end }
+        }
+    }
+
+    pub fn inclusive(first: i64, last: i64) -> Interval {
+        Interval::new(first, last.saturating_add(1))
+    }
+
+    pub fn with_length(start: i64, length: i64) -> Interval {
+        Interval::new(start, start.saturating_add(length))
+    }
+
+    pub fn empty() -> Interval {
+        Interval { start: 0, end: 0 }
+    }
+
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+
+    pub fn end(&self) -> i64 {
+        self.end
+    }
+
+    /// Last value inside the interval. Meaningless for empty intervals.
+    pub fn last(&self) -> i64 {
+        self.end - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    pub fn len(&self) -> i64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        self.start <= value && value < self.end
+    }
+
+    pub fn intersection(&self, other: &Interval) -> Interval {
+        Interval::new(cmp::max(self.start, other.start), cmp::min(self.end, other.end))
+    }
+
+    pub fn difference(&self, other: &Interval) -> IntervalSet {
+        let overlap = self.intersection(other);
+        if overlap.is_empty() {
+            return IntervalSet::from(*self);
+        }
+        IntervalSet::from_iter([
+            Interval::new(self.start, overlap.start),
+            Interval::new(overlap.end, self.end),
+        ])
+    }
+
+    /// Splits into the values below `point` and the values at or above it. Either side may be empty.
+    pub fn split_at(&self, point: i64) -> (Interval, Interval) {
+        let point = point.clamp(self.start, self.end);
+        (Interval::new(self.start, point), Interval::new(point, self.end))
+    }
+
+    pub fn shift(&self, offset: i64) -> Interval {
+        if self.is_empty() {
+            return *self;
+        }
+        Interval::new(self.start + offset, self.end + offset)
+    }
+}
+
+/// Set of integers stored as sorted, disjoint and non-adjacent intervals.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct IntervalSet {
+    intervals: Vec<Interval>,
+}
+
+impl fmt::Debug for IntervalSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.intervals.iter()).finish()
+    }
+}
+
+impl From<Interval> for IntervalSet {
+    fn from(interval: Interval) -> IntervalSet {
+        IntervalSet::from_iter([interval])
+    }
+}
+
+impl FromIterator<Interval> for IntervalSet {
+    fn from_iter<T: IntoIterator<Item = Interval>>(iter: T) -> IntervalSet {
+        let mut intervals = iter.into_iter().filter(|i| !i.is_empty()).collect::<Vec<_>>();
+        intervals.sort_by_key(|i| i.start);
+
+        let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+        for interval in intervals {
+            match merged.last_mut() {
+                Some(last) if interval.start <= last.end => last.end = cmp::max(last.end, interval.end),
+                _ => merged.push(interval),
+            }
+        }
+        IntervalSet { intervals: merged }
+    }
+}
+
+impl IntervalSet {
+    pub fn new() -> IntervalSet {
+        IntervalSet::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Number of integers in the set.
+    pub fn len(&self) -> i64 {
+        self.intervals.iter().map(|i| i.len()).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Interval> {
+        self.intervals.iter()
+    }
+
+    pub fn min(&self) -> Option<i64> {
+        self.intervals.first().map(|i| i.start)
+    }
+
+    pub fn max(&self) -> Option<i64> {
+        self.intervals.last().map(|i| i.last())
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        let index = self.intervals.partition_point(|i| i.end <= value);
+        self.intervals.get(index).is_some_and(|i| i.contains(value))
+    }
+
+    pub fn insert(&mut self, interval: Interval) {
+        *self = self.union(&IntervalSet::from(interval));
+    }
+
+    pub fn union(&self, other: &IntervalSet) -> IntervalSet {
+        self.intervals.iter().chain(other.intervals.iter()).copied().collect()
+    }
+
+    pub fn intersection(&self, other: &IntervalSet) -> IntervalSet {
+        let mut result = Vec::new();
+        let (mut a, mut b) = (0, 0);
+        while a < self.intervals.len() && b < other.intervals.len() {
+            let (left, right) = (self.intervals[a], other.intervals[b]);
+            result.push(left.intersection(&right));
+            if left.end < right.end {
+                a += 1;
+            } else {
+                b += 1;
+            }
+        }
+        result.into_iter().collect()
+    }
+
+    pub fn difference(&self, other: &IntervalSet) -> IntervalSet {
+        let mut result = Vec::new();
+        let mut b = 0;
+        for interval in &self.intervals {
+            let mut rest = *interval;
+            // skip everything that ends before the current interval starts
+            while b < other.intervals.len() && other.intervals[b].end <= rest.start {
+                b += 1;
+            }
+            let mut i = b;
+            while !rest.is_empty() && i < other.intervals.len() && other.intervals[i].start < rest.end {
+                let (below, _) = rest.split_at(other.intervals[i].start);
+                result.push(below);
+                rest = rest.split_at(other.intervals[i].end).1;
+                i += 1;
+            }
+            result.push(rest);
+        }
+        result.into_iter().collect()
+    }
+
+    pub fn split_at(&self, point: i64) -> (IntervalSet, IntervalSet) {
+        let (below, above): (Vec<_>, Vec<_>) = self.intervals.iter().map(|i| i.split_at(point)).unzip();
+        (below.into_iter().collect(), above.into_iter().collect())
+    }
+
+    pub fn shift(&self, offset: i64) -> IntervalSet {
+        IntervalSet { intervals: self.intervals.iter().map(|i| i.shift(offset)).collect() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use proptest::prelude::*;
+    use super::*;
+
+    fn values(set: &IntervalSet) -> BTreeSet<i64> {
+        set.iter().flat_map(|i| i.start()..i.end()).collect()
+    }
+
+    fn interval_strategy() -> impl Strategy<Value = Interval> {
+        (-50i64..50, 0i64..20).prop_map(|(start, length)| Interval::with_length(start, length))
+    }
+
+    fn set_strategy() -> impl Strategy<Value = IntervalSet> {
+        prop::collection::vec(interval_strategy(), 0..6).prop_map(IntervalSet::from_iter)
+    }
+
+    #[test]
+    fn test_interval_empty_is_normalised() {
+        assert_eq!(Interval::new(5, 5), Interval::empty());
+        assert_eq!(Interval::new(7, 3), Interval::empty());
+        assert_eq!(Interval::inclusive(3, 3).len(), 1);
+    }
+
+    #[test]
+    fn test_interval_difference() {
+        let i = Interval::new(0, 10);
+        assert_eq!(i.difference(&Interval::new(3, 5)), IntervalSet::from_iter([Interval::new(0, 3), Interval::new(5, 10)]));
+        assert_eq!(i.difference(&Interval::new(-5, 5)), IntervalSet::from(Interval::new(5, 10)));
+        assert_eq!(i.difference(&Interval::new(-5, 15)), IntervalSet::new());
+    }
+
+    #[test]
+    fn test_set_merges_adjacent() {
+        let set = IntervalSet::from_iter([Interval::new(5, 8), Interval::new(0, 5), Interval::new(10, 12)]);
+        assert_eq!(set.iter().copied().collect::<Vec<_>>(), vec![Interval::new(0, 8), Interval::new(10, 12)]);
+        assert_eq!(set.len(), 10);
+        assert_eq!(set.min(), Some(0));
+        assert_eq!(set.max(), Some(11));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_union(a in set_strategy(), b in set_strategy()) {
+            let expected = values(&a).union(&values(&b)).copied().collect::<BTreeSet<_>>();
+            prop_assert_eq!(values(&a.union(&b)), expected);
+        }
+
+        #[test]
+        fn prop_intersection(a in set_strategy(), b in set_strategy()) {
+            let expected = values(&a).intersection(&values(&b)).copied().collect::<BTreeSet<_>>();
+            prop_assert_eq!(values(&a.intersection(&b)), expected);
+        }
+
+        #[test]
+        fn prop_difference(a in set_strategy(), b in set_strategy()) {
+            let expected = values(&a).difference(&values(&b)).copied().collect::<BTreeSet<_>>();
+            prop_assert_eq!(values(&a.difference(&b)), expected);
+        }
+
+        #[test]
+        fn prop_split_at(a in set_strategy(), point in -60i64..80) {
+            let (below, above) = a.split_at(point);
+            prop_assert!(values(&below).iter().all(|v| *v < point));
+            prop_assert!(values(&above).iter().all(|v| *v >= point));
+            prop_assert_eq!(below.union(&above), a);
+        }
+
+        #[test]
+        fn prop_shift(a in set_strategy(), offset in -100i64..100) {
+            let expected = values(&a).iter().map(|v| v + offset).collect::<BTreeSet<_>>();
+            prop_assert_eq!(values(&a.shift(offset)), expected);
+            prop_assert_eq!(a.shift(offset).shift(-offset), a);
+        }
+
+        #[test]
+        fn prop_contains(a in set_strategy(), value in -60i64..80) {
+            prop_assert_eq!(a.contains(value), values(&a).contains(&value));
+        }
+
+        #[test]
+        fn prop_set_is_canonical(a in set_strategy()) {
+            let intervals = a.iter().collect::<Vec<_>>();
+            prop_assert!(intervals.iter().all(|i| !i.is_empty()));
+            prop_assert!(intervals.windows(2).all(|w| w[0].end() < w[1].start()));
+        }
+    }
+}
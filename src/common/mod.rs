@@ -0,0 +1,9 @@
+pub mod dot;
+pub mod geometry;
+pub mod graph;
+pub mod hyperrect;
+pub mod image;
+pub mod interval;
+pub mod parallel;
+pub mod parsing;
+pub mod render;
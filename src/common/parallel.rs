@@ -0,0 +1,50 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Maps `f` over `items`, spreading the work over the rayon thread pool when the `parallel` feature is enabled.
+/// The results are always in the same order as `items`, so callers get identical answers either way.
+pub fn par_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    {
+        items.par_iter().map(f).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        items.iter().map(f).collect()
+    }
+}
+
+/// Sets the number of worker threads used by `par_map`. Has to be called before any parallel work starts.
+pub fn set_threads(threads: usize) -> Result<(), String> {
+    #[cfg(feature = "parallel")]
+    {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|e| e.to_string())
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        match threads {
+            1 => Ok(()),
+            _ => Err("built without the `parallel` feature, running on a single thread".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_par_map_keeps_order() {
+        let items = (0..1000u64).collect::<Vec<_>>();
+        let expected = items.iter().map(|i| i * i).collect::<Vec<_>>();
+        assert_eq!(par_map(&items, |i| i * i), expected);
+    }
+}
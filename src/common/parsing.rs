@@ -0,0 +1,168 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use nom::{Finish, IResult};
+use nom::character::complete::{alphanumeric1, anychar, char, digit1, one_of, space0, space1};
+use nom::combinator::{all_consuming, map_opt, map_res, opt, recognize};
+use nom::multi::{many1, separated_list1};
+use nom::sequence::{delimited, pair, separated_pair};
+
+/// Parse failure together with the (1-based) line of the input it happened on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(line: usize, message: impl Into<String>) -> ParseError {
+        ParseError { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+pub fn unsigned<T: FromStr>(input: &str) -> IResult<&str, T> {
+    map_res(digit1, str::parse)(input)
+}
+
+pub fn signed<T: FromStr>(input: &str) -> IResult<&str, T> {
+    map_res(recognize(pair(opt(one_of("+-")), digit1)), str::parse)(input)
+}
+
+/// Whitespace separated unsigned integers, e.g. ` 41 48  6 `.
+pub fn unsigned_list<T: FromStr>(input: &str) -> IResult<&str, Vec<T>> {
+    delimited(space0, separated_list1(space1, unsigned), space0)(input)
+}
+
+/// Whitespace separated signed integers, e.g. `10 -3 +7`.
+pub fn signed_list<T: FromStr>(input: &str) -> IResult<&str, Vec<T>> {
+    delimited(space0, separated_list1(space1, signed), space0)(input)
+}
+
+/// `key: value`, e.g. `Game 12: 3 blue, 4 red` or `seeds: 79 14 55 13`.
+pub fn key_value<'a, K, V>(
+    key: impl FnMut(&'a str) -> IResult<&'a str, K>,
+    value: impl FnMut(&'a str) -> IResult<&'a str, V>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (K, V)> {
+    separated_pair(key, pair(char(':'), space0), value)
+}
+
+/// `Name = (Left, Right)`, e.g. `AAA = (BBB, CCC)`.
+pub fn named_pair(input: &str) -> IResult<&str, (&str, (&str, &str))> {
+    let pair_values = separated_pair(alphanumeric1, pair(char(','), space0), alphanumeric1);
+    separated_pair(
+        alphanumeric1,
+        delimited(space0, char('='), space0),
+        delimited(char('('), pair_values, char(')')),
+    )(input)
+}
+
+/// One row of a char grid, converting every character with `cell`. Fails on characters `cell` rejects.
+pub fn grid_row<'a, T>(cell: impl Fn(char) -> Option<T>) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<T>> {
+    many1(map_opt(anychar, cell))
+}
+
+/// Splits the input at blank lines. Every block is paired with the line number of its first line.
+pub fn blocks(input: &str) -> Vec<(usize, &str)> {
+    let mut result = Vec::new();
+    let mut block_start: Option<(usize, usize)> = None;
+    let mut offset = 0;
+    for (index, line) in input.split('\n').enumerate() {
+        let line_end = offset + line.len();
+        match (line.trim().is_empty(), block_start) {
+            (true, Some((first_line, start))) => {
+                result.push((first_line, input[start..offset].trim_end()));
+                block_start = None;
+            }
+            (false, None) => block_start = Some((index + 1, offset)),
+            _ => {}
+        }
+        offset = line_end + 1;
+    }
+    if let Some((first_line, start)) = block_start {
+        result.push((first_line, input[start..].trim_end()));
+    }
+    result
+}
+
+/// Runs `parser` on a single line, which it has to consume completely.
+pub fn parse_line<'a, T>(
+    line_number: usize,
+    line: &'a str,
+    parser: impl FnMut(&'a str) -> IResult<&'a str, T>,
+) -> Result<T, ParseError> {
+    match all_consuming(parser)(line).finish() {
+        Ok((_, value)) => Ok(value),
+        Err(e) => {
+            let column = line.len() - e.input.len() + 1;
+            Err(ParseError::new(line_number, format!("unexpected input at column {}: {:?}", column, line)))
+        }
+    }
+}
+
+/// Parses every line of `input` with `parser`. `first_line` is the line number of the first line of `input`.
+pub fn parse_lines<'a, T>(
+    input: &'a str,
+    first_line: usize,
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, T>,
+) -> Result<Vec<T>, ParseError> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(index, line)| parse_line(first_line + index, line, &mut parser))
+        .collect()
+}
+
+/// Parses a rectangular grid of characters, converting every cell with `cell`.
+pub fn char_grid<T>(input: &str, first_line: usize, cell: impl Fn(char) -> Option<T>) -> Result<Vec<Vec<T>>, ParseError> {
+    let grid = parse_lines(input, first_line, grid_row(&cell))?;
+    if let Some((index, _)) = grid.iter().enumerate().find(|(_, row)| row.len() != grid[0].len()) {
+        return Err(ParseError::new(first_line + index, format!("expected {} cells in every row", grid[0].len())));
+    }
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use nom::bytes::complete::tag;
+    use super::*;
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(parse_line(1, " 41 48  6 ", unsigned_list::<u32>), Ok(vec![41, 48, 6]));
+        assert_eq!(parse_line(1, "10 -3 +7", signed_list::<i64>), Ok(vec![10, -3, 7]));
+        assert!(parse_line(1, "10 -3", unsigned_list::<u32>).is_err());
+    }
+
+    #[test]
+    fn test_key_value_and_pairs() {
+        let seeds = parse_line(1, "seeds: 79 14", key_value(tag("seeds"), unsigned_list::<u64>));
+        assert_eq!(seeds, Ok(("seeds", vec![79, 14])));
+        assert_eq!(parse_line(1, "11A = (11B, XXX)", named_pair), Ok(("11A", ("11B", "XXX"))));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let input = "seeds: 1\n\nfirst map:\n1 2 3\n\n\nsecond map:\n4 5 6\n";
+        assert_eq!(blocks(input), vec![(1, "seeds: 1"), (3, "first map:\n1 2 3"), (7, "second map:\n4 5 6")]);
+    }
+
+    #[test]
+    fn test_errors_carry_line_numbers() {
+        let error = parse_lines("1 2\n3 x\n", 5, unsigned_list::<u32>).unwrap_err();
+        assert_eq!(error.line, 6);
+
+        let cell = |c| match c { '.' => Some(false), '#' => Some(true), _ => None };
+        assert_eq!(char_grid(".#\n#.", 1, cell), Ok(vec![vec![false, true], vec![true, false]]));
+        assert_eq!(char_grid(".#\n#?", 1, cell).unwrap_err().line, 2);
+        assert_eq!(char_grid(".#\n#", 1, cell).unwrap_err().line, 2);
+    }
+}
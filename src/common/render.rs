@@ -0,0 +1,171 @@
+use std::io;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Color {
+    Default,
+    Gray,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn ansi_code(&self) -> String {
+        match self {
+            Color::Default => "\x1b[0m".to_string(),
+            Color::Gray => "\x1b[90m".to_string(),
+            Color::Red => "\x1b[91m".to_string(),
+            Color::Green => "\x1b[92m".to_string(),
+            Color::Yellow => "\x1b[93m".to_string(),
+            Color::Blue => "\x1b[94m".to_string(),
+            Color::Magenta => "\x1b[95m".to_string(),
+            Color::Cyan => "\x1b[96m".to_string(),
+            Color::White => "\x1b[97m".to_string(),
+            Color::Rgb(r, g, b) => format!("\x1b[38;2;{};{};{}m", r, g, b),
+        }
+    }
+
+    /// Pixel value of the colour, used when exporting images.
+    pub fn rgb(&self) -> [u8; 3] {
+        match self {
+            Color::Default => [24, 24, 24],
+            Color::Gray => [64, 64, 64],
+            Color::Red => [230, 60, 60],
+            Color::Green => [80, 200, 90],
+            Color::Yellow => [240, 210, 60],
+            Color::Blue => [60, 110, 230],
+            Color::Magenta => [210, 80, 210],
+            Color::Cyan => [70, 200, 210],
+            Color::White => [240, 240, 240],
+            Color::Rgb(r, g, b) => [*r, *g, *b],
+        }
+    }
+
+    /// Colour for `value` on a dark blue to yellow scale, where `max` maps to the hottest colour.
+    pub fn heat(value: usize, max: usize) -> Color {
+        if value == 0 || max == 0 {
+            return Color::Rgb(16, 16, 48);
+        }
+        let t = value as f64 / max as f64;
+        let channel = |from: f64, to: f64| (from + (to - from) * t) as u8;
+        Color::Rgb(channel(60.0, 255.0), channel(20.0, 230.0), channel(140.0, 40.0))
+    }
+}
+
+/// A single coloured character of a rendered grid.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Glyph {
+    pub symbol: char,
+    pub color: Color,
+}
+
+impl Glyph {
+    pub fn new(symbol: char, color: Color) -> Glyph {
+        Glyph { symbol, color }
+    }
+}
+
+/// Puzzle state that can be drawn as a grid of coloured characters.
+pub trait Render {
+    fn glyphs(&self) -> Vec<Vec<Glyph>>;
+
+    fn to_ansi(&self) -> String {
+        let mut result = String::new();
+        for row in self.glyphs() {
+            let mut current_color = Color::Default;
+            for glyph in row {
+                if glyph.color != current_color {
+                    result.push_str(&glyph.color.ansi_code());
+                    current_color = glyph.color;
+                }
+                result.push(glyph.symbol);
+            }
+            result.push_str(&Color::Default.ansi_code());
+            result.push('\n');
+        }
+        result
+    }
+}
+
+/// Settings of the `--visualize` flag.
+#[derive(Debug)]
+pub struct Visualizer {
+    pub animate: bool,
+    pub frames_per_second: u32,
+    /// Set once writing to stdout failed, e.g. because it was piped into `head`. Nothing is drawn after that.
+    closed: AtomicBool,
+}
+
+impl Visualizer {
+    pub fn new(animate: bool, frames_per_second: u32) -> Visualizer {
+        Visualizer { animate, frames_per_second, closed: AtomicBool::new(false) }
+    }
+
+    /// Prints the final state.
+    pub fn show(&self, state: &impl Render) {
+        self.draw(&state.to_ansi());
+    }
+
+    /// Prints an intermediate state, replacing the previous frame. Does nothing unless animating.
+    pub fn frame(&self, state: &impl Render) {
+        if !self.animate {
+            return;
+        }
+        // move the cursor home and clear the screen before drawing
+        if self.draw(&format!("\x1b[H\x1b[2J{}", state.to_ansi())) {
+            thread::sleep(Duration::from_secs_f64(1.0 / self.frames_per_second.max(1) as f64));
+        }
+    }
+
+    /// Writes `text` to stdout, and returns whether that worked. Stops drawing for good on the first failure.
+    fn draw(&self, text: &str) -> bool {
+        if self.closed.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut stdout = io::stdout().lock();
+        let written = write!(stdout, "{}", text).and_then(|_| stdout.flush()).is_ok();
+        if !written {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+        written
+    }
+}
+
+/// Most intermediate frames an animation gets, in the terminal or exported. Keeps long animations watchable
+/// and exported GIFs small.
+pub const MAX_FRAMES: usize = 100;
+
+/// Step size that keeps an animation of `steps` states to at most `MAX_FRAMES` frames.
+pub fn frame_step(steps: usize) -> usize {
+    steps.div_ceil(MAX_FRAMES).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Checkers;
+
+    impl Render for Checkers {
+        fn glyphs(&self) -> Vec<Vec<Glyph>> {
+            vec![
+                vec![Glyph::new('#', Color::Red), Glyph::new('.', Color::Default)],
+                vec![Glyph::new('.', Color::Default), Glyph::new('#', Color::Red)],
+            ]
+        }
+    }
+
+    #[test]
+    fn test_to_ansi() {
+        assert_eq!(Checkers.to_ansi(), "\x1b[91m#\x1b[0m.\x1b[0m\n.\x1b[91m#\x1b[0m\n");
+    }
+}
@@ -0,0 +1,3 @@
+pub mod aoc;
+pub mod common;
+pub mod solutions;
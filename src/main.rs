@@ -1,11 +1,17 @@
 extern crate core;
 
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
 use clap::Parser;
 use dotenv;
 use tokio;
 
-mod aoc;
-mod solutions;
+use aoc_2023::{aoc, solutions};
+use aoc_2023::common::image::ImageExporter;
+use aoc_2023::common::parallel;
+use aoc_2023::common::render::Visualizer;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -18,6 +24,82 @@ struct Args {
 
     #[arg(long)]
     solve: bool,
+
+    /// Number of worker threads for days that split their work (requires the `parallel` feature)
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Render the puzzle state as a coloured grid (days 10, 14, 16, 17 and 18)
+    #[arg(long)]
+    visualize: bool,
+
+    /// Animate the visualization step by step
+    #[arg(long, requires = "visualize")]
+    animate: bool,
+
+    /// Animation speed in frames per second
+    #[arg(long, default_value_t = 10, requires = "animate")]
+    speed: u32,
+
+    /// Save a picture of the puzzle state as .png, .ppm or an animated .gif (days 10, 14, 16, 17 and 18)
+    #[arg(long, value_name = "PATH")]
+    export_image: Option<PathBuf>,
+
+    /// Write the puzzle input as a Graphviz DOT graph (days 8 and 19)
+    #[arg(long, value_name = "PATH")]
+    dot: Option<PathBuf>,
+
+    /// Explain how the answer was derived (days 5, 10, 13, 16, 17, 18 and 19)
+    #[arg(long)]
+    explain: bool,
+
+    /// Number of copies each day 12 row is unfolded into (defaults to 1, or 5 with --adv)
+    #[arg(long)]
+    fold: Option<usize>,
+
+    /// List up to this many valid arrangements of every day 12 row
+    #[arg(long, value_name = "COUNT")]
+    arrangements: Option<usize>,
+
+    /// Print a random valid arrangement of every day 12 row
+    #[arg(long)]
+    sample: bool,
+
+    /// Number of smudges on every day 13 mirror (defaults to 0, or 1 with --adv)
+    #[arg(long)]
+    smudges: Option<usize>,
+
+    /// Day 14 tilt program such as `NWSE*1000000000` or `N,E,N`; prints the platform afterwards
+    #[arg(long, value_name = "PROGRAM")]
+    tilt: Option<String>,
+
+    /// Edge (N, E, S or W) to measure the day 14 load against
+    #[arg(long, value_name = "EDGE")]
+    load_edge: Option<char>,
+
+    /// Fewest blocks the day 17 crucible moves in a row (defaults to 1, or 4 with --adv)
+    #[arg(long, value_name = "BLOCKS")]
+    min_straight: Option<usize>,
+
+    /// Most blocks the day 17 crucible moves in a row (defaults to 3, or 10 with --adv)
+    #[arg(long, value_name = "BLOCKS")]
+    max_straight: Option<usize>,
+
+    /// Day 17 start tile as ROW,COLUMN counting from 1 (defaults to the top left)
+    #[arg(long, value_name = "ROW,COLUMN")]
+    start: Option<String>,
+
+    /// Day 17 goal tile as ROW,COLUMN counting from 1 (defaults to the bottom right)
+    #[arg(long, value_name = "ROW,COLUMN")]
+    goal: Option<String>,
+
+    /// Values a day 19 attribute can take, e.g. `x=1..=10000` (defaults to 1..=4000); can be repeated
+    #[arg(long = "domain", value_name = "NAME=FIRST..=LAST")]
+    domains: Vec<String>,
+
+    /// Only count day 19 parts meeting a condition such as `x<1000`; can be repeated
+    #[arg(long = "constraint", value_name = "CONDITION")]
+    constraints: Vec<String>,
 }
 #[tokio::main]
 async fn main() {
@@ -25,6 +107,11 @@ async fn main() {
     let aoc_session = std::env::var("AOC_SESSION").unwrap_or(String::new());
 
     let args = Args::parse();
+    if let Some(threads) = args.threads {
+        if let Err(e) = parallel::set_threads(threads) {
+            eprintln!("Warning: {}", e);
+        }
+    }
     let mut input_data: Option<String> = None;
     if args.solve {
         let data = aoc::datafiles::load_data(2023, args.day, aoc_session)
@@ -33,7 +120,33 @@ async fn main() {
         input_data = Some(data);
     }
 
-    let solution = solutions::solve_for_day(args.day, input_data, args.adv);
+    let options = solutions::Options {
+        visualizer: args.visualize.then_some(Visualizer::new(args.animate, args.speed)),
+        exporter: args.export_image.map(ImageExporter::new),
+        dot: args.dot,
+        explain: args.explain,
+        fold: args.fold,
+        arrangements: args.arrangements,
+        sample: args.sample,
+        smudges: args.smudges,
+        tilt: args.tilt,
+        load_edge: args.load_edge,
+        min_straight: args.min_straight,
+        max_straight: args.max_straight,
+        start: args.start,
+        goal: args.goal,
+        domains: args.domains,
+        constraints: args.constraints,
+    };
 
-    println!("Solution: {}", solution);
+    match solutions::solve_for_day(args.day, input_data, args.adv, &options) {
+        Ok(solution) => {
+            // stdout may be closed already, e.g. when a visualization is piped into `head`
+            let _ = writeln!(io::stdout(), "Solution: {}", solution);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
\ No newline at end of file
@@ -1,4 +1,10 @@
-use regex::Regex;
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, space0, space1};
+use nom::IResult;
+use nom::multi::separated_list1;
+use nom::sequence::{pair, preceded, separated_pair};
+use crate::common::parsing::{key_value, parse_lines, unsigned, ParseError};
 
 static EXAMPLE: &str = "Game 1: 3 blue, 4 red; 1 red, 2 green, 6 blue; 2 green
 Game 2: 1 blue, 2 green; 3 green, 4 blue, 1 red; 1 green, 1 blue
@@ -6,17 +12,19 @@ Game 3: 8 green, 6 blue, 20 red; 5 blue, 4 red, 13 green; 5 green, 1 red
 Game 4: 1 green, 3 red, 6 blue; 3 green, 6 red; 3 green, 15 blue, 14 red
 Game 5: 6 red, 1 blue, 3 green; 2 blue, 1 red, 2 green";
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+type Game = (u32, Vec<(u32, u32, u32)>);
+
+pub fn solve(input_data: Option<String>, advanced: bool) -> Result<String, ParseError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
 
-    let games: Vec<(u32, Vec<(u32, u32, u32)>)> = data.lines().map(parse_game).collect();
+    let games: Vec<Game> = parse_lines(&data, 1, parse_game)?;
 
     if advanced {
         let sum_games: u32 = games.iter().map(|(_, r)| game_power(r)).sum();
-        format!("{}", sum_games)
+        Ok(format!("{}", sum_games))
     } else {
         let sum_games: u32 = games.iter().filter_map(|(g, r)| if game_is_valid(r) { Some(g) } else { None }).sum::<u32>();
-        format!("{}", sum_games)
+        Ok(format!("{}", sum_games))
     }
 }
 
@@ -31,32 +39,24 @@ fn game_power(reveals: &Vec<(u32, u32, u32)>) -> u32 {
     return min_red * min_green * min_blue;
 }
 
-fn parse_game(line: &str) -> (u32, Vec<(u32, u32, u32)>) {
-    let game_re = Regex::new("^Game ([0-9]+):").unwrap();
-    let game_number = game_re.captures(line).unwrap().get(1).unwrap().as_str().parse::<u32>().unwrap();
-
-    let parts: Vec<&str> = line.split(":").collect();
-    let reveals = parts[1].split(";").map(parse_reveal);
-    return (game_number, reveals.collect());
+fn parse_game(line: &str) -> IResult<&str, Game> {
+    // example: Game 1: 3 blue, 4 red; 1 red, 2 green, 6 blue; 2 green
+    let game_number = preceded(tag("Game "), unsigned);
+    let reveals = separated_list1(pair(char(';'), space0), parse_reveal);
+    key_value(game_number, reveals)(line)
 }
 
-fn parse_reveal(reveal: &str) -> (u32, u32, u32) {
-    let red_re = Regex::new("([0-9]+) red").unwrap();
-    let green_re = Regex::new("([0-9]+) green").unwrap();
-    let blue_re = Regex::new("([0-9]+) blue").unwrap();
-
-    let capture = |re: Regex| -> u32 {
-        re.captures_iter(reveal)
-            .map(|c| c
-                .get(1)
-                .unwrap()
-                .as_str()
-                .parse::<u32>().unwrap())
-            .sum()
+fn parse_reveal(reveal: &str) -> IResult<&str, (u32, u32, u32)> {
+    // example: 1 red, 2 green, 6 blue
+    let color = alt((tag("red"), tag("green"), tag("blue")));
+    let (rest, cubes) = separated_list1(pair(char(','), space0), separated_pair(unsigned::<u32>, space1, color))(reveal)?;
+
+    let capture = |name: &str| -> u32 {
+        cubes.iter().filter(|(_, c)| *c == name).map(|(count, _)| count).sum()
     };
 
-    let red = capture(red_re);
-    let green = capture(green_re);
-    let blue = capture(blue_re);
-    return (red, green, blue);
-}
\ No newline at end of file
+    let red = capture("red");
+    let green = capture("green");
+    let blue = capture("blue");
+    Ok((rest, (red, green, blue)))
+}
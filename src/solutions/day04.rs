@@ -1,5 +1,10 @@
 use std::cmp;
 use std::collections::{HashSet};
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, space1};
+use nom::IResult;
+use nom::sequence::{pair, preceded, separated_pair};
+use crate::common::parsing::{key_value, parse_lines, unsigned, unsigned_list, ParseError};
 
 static EXAMPLE: &str = "Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53
 Card 2: 13 32 20 16 61 | 61 30 68 82 17 32 24 19
@@ -13,27 +18,28 @@ struct Card {
     chosen_numbers: HashSet<u32>,
 }
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+pub fn solve(input_data: Option<String>, advanced: bool) -> Result<String, ParseError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
-    let cards: Vec<Card> = data.lines().map(parse_card).collect();
+    let cards: Vec<Card> = parse_lines(&data, 1, parse_card)?;
     return if advanced {
         let solve_for_index = |i| solve_advanced_for_cards(&cards, i);
         let points: u32 = (0..cards.len()).map(solve_for_index).sum();
-        format!("{}", points)
+        Ok(format!("{}", points))
     } else {
         let points: u32 = cards.iter().map(solve_simple_for_card).sum();
-        format!("{}", points)
+        Ok(format!("{}", points))
     }
 }
 
-fn parse_card(card: &str) -> Card {
-    let all_numbers = card.split(":").collect::<Vec<&str>>()[1].split("|").collect::<Vec<&str>>();
-    let winning_numbers = parse_number_series(all_numbers[0]);
-    let chosen_numbers: HashSet<u32> = parse_number_series(all_numbers[1]);
-    Card {
-        winning_numbers,
-        chosen_numbers,
-    }
+fn parse_card(card: &str) -> IResult<&str, Card> {
+    // example: Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53
+    let card_number = preceded(pair(tag("Card"), space1), unsigned::<u32>);
+    let numbers = separated_pair(unsigned_list, char('|'), unsigned_list);
+    let (rest, (_, (winning_numbers, chosen_numbers))) = key_value(card_number, numbers)(card)?;
+    Ok((rest, Card {
+        winning_numbers: winning_numbers.into_iter().collect(),
+        chosen_numbers: chosen_numbers.into_iter().collect(),
+    }))
 }
 
 fn solve_simple_for_card(card: &Card) -> u32 {
@@ -59,11 +65,3 @@ fn solve_advanced_for_cards(cards: &Vec<Card>, index: usize) -> u32 {
         1
     }
 }
-
-fn parse_number_series(series: &str) -> HashSet<u32> {
-    if series.len() > 0 {
-        series.split(" ").filter(|n| !n.is_empty()).map(|n: &str| n.parse::<u32>().unwrap()).collect()
-    } else {
-        HashSet::new()
-    }
-}
\ No newline at end of file
@@ -1,4 +1,10 @@
-use std::collections::HashSet;
+use itertools::Itertools;
+use nom::bytes::complete::tag;
+use nom::character::complete::{alpha1, space1};
+use nom::sequence::{preceded, separated_pair, terminated, tuple};
+use crate::common::interval::{Interval, IntervalSet};
+use crate::common::parsing::{blocks, key_value, parse_line, parse_lines, unsigned, unsigned_list, ParseError};
+use crate::solutions::Options;
 
 static EXAMPLE: &str = "seeds: 79 14 55 13
 
@@ -36,182 +42,405 @@ humidity-to-location map:
 
 #[derive(Debug)]
 struct Range {
-    dst_start: u64,
-    src_start: u64,
-    length: u64,
+    src: Interval,
+    offset: i64,
 }
 
 impl Range {
-    fn src_to_dst(&self, start: u64, range: u64, is_mapped: bool) -> Vec<(u64, u64, bool)> {
-        if is_mapped {
-            return Vec::from([(start, range, true)])
-        }
-        let end = start + range - 1;
-
-        // if totally outside the range
-        if end < self.src_start || start > (self.src_start + self.length - 1) {
-            return Vec::from([(start, range, false)])
-        }
+    /// Maps the part of `input` covered by this range. Returns the mapped values and the unmapped remainder.
+    fn src_to_dst(&self, input: &IntervalSet) -> (IntervalSet, IntervalSet) {
+        let covered = input.intersection(&IntervalSet::from(self.src));
+        let rest = input.difference(&covered);
+        (covered.shift(self.offset), rest)
+    }
 
-        // if totally inside the range
-        if start >= self.src_start && (start + range - 1) <= (self.src_start + self.length - 1) {
-            return Vec::from([(self.dst_start + (start - self.src_start), range, true)])
-        }
+    fn dst(&self) -> Interval {
+        self.src.shift(self.offset)
+    }
+}
 
-        // if encapsulates the range
-        if start <= self.src_start && (end >= (self.src_start + self.length - 1)) {
-            let mut ret = Vec::new();
-            ret.push((start, self.src_start - start, false));
-            ret.push((self.dst_start, self.length, true));
-            ret.push((self.src_start + self.length, start + range - self.src_start - self.length, false));
-            return ret
-        }
+/// Seeds that all take the same path through the maps. `offsets[i]` is what has been added to the seed
+/// numbers after the first `i` maps, so the values in every category are `seeds` shifted by that offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Lineage {
+    seeds: Interval,
+    offsets: Vec<i64>,
+}
 
-        // if partially in the range
-        let mut ret = Vec::new();
+impl Lineage {
+    fn new(seeds: Interval) -> Lineage {
+        Lineage { seeds, offsets: vec![0] }
+    }
 
-        // if start is outside
-        if start < self.src_start {
-            ret.push((start, self.src_start - start, false));
-            ret.push((self.dst_start, start + range - self.src_start, true));
-            return ret
-        }
+    fn current(&self) -> Interval {
+        self.seeds.shift(*self.offsets.last().unwrap())
+    }
 
-        // if end is outside
-        if end > (self.src_start + self.length - 1) {
-            let overlap_range = range + (self.src_start + self.length - 1) - end;
-            ret.push((self.dst_start + start - self.src_start, overlap_range, true));
-            ret.push((self.src_start + self.length, range - overlap_range, false));
-            return ret
-        }
-        panic!("Shouldn't be here, the possibilities are exhausted");
+    /// The same path taken by a subset of the current values, continued with `offset`.
+    fn extend(&self, values: Interval, offset: i64) -> Lineage {
+        let total = *self.offsets.last().unwrap();
+        let mut offsets = self.offsets.clone();
+        offsets.push(total + offset);
+        Lineage { seeds: values.shift(-total), offsets }
     }
 }
 
 #[derive(Debug)]
 struct RangeMap {
+    source: String,
+    destination: String,
     ranges: Vec<Range>,
 }
 
 impl RangeMap {
-    fn src_to_dst(&self, src: u64, src_range: u64) -> Vec<(u64, u64)> {
-        let mut ret = Vec::from([(src, src_range, false)]);
-        for r in &self.ranges {
-            ret = ret.iter().map(|(ms, mr, is_mapped)| r.src_to_dst(*ms, *mr, *is_mapped)).flatten().collect();
+    /// Like `src_to_dst`, but remembers which seeds every mapped interval came from.
+    fn trace(&self, input: &[Lineage]) -> Vec<Lineage> {
+        let mut result = Vec::new();
+        for lineage in input {
+            let mut unmapped = IntervalSet::from(lineage.current());
+            for r in &self.ranges {
+                let (mapped, rest) = r.src_to_dst(&unmapped);
+                result.extend(mapped.iter().map(|m| lineage.extend(m.shift(-r.offset), r.offset)));
+                unmapped = rest;
+            }
+            result.extend(unmapped.iter().map(|u| lineage.extend(*u, 0)));
         }
-        ret.iter().map(|(s, r, _)| (*s, *r)).collect()
+        result
     }
-}
 
+    /// Every source value that ends up in `output`, the inverse of the map. Read off the normalized pieces,
+    /// so earlier ranges win where ranges overlap, like everywhere else.
+    fn dst_to_src(&self, output: &IntervalSet) -> IntervalSet {
+        self.normalized()
+            .iter()
+            .map(|p| output.intersection(&IntervalSet::from(p.dst())).shift(-p.offset))
+            .fold(IntervalSet::new(), |acc, s| acc.union(&s))
+    }
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
-    let data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
-    let categories_strings = data.split("\n\n").collect::<Vec<&str>>();
+    fn identity(category: &str) -> RangeMap {
+        RangeMap { source: category.to_string(), destination: category.to_string(), ranges: vec![] }
+    }
 
-    let seeds = parse_seeds(categories_strings[0], advanced);
-    let categories: Vec<RangeMap> = categories_strings[1..].iter().map(|c| parse_map(c)).collect();
+    /// The same mapping as sorted, non-overlapping ranges covering every value, with the implicit identity
+    /// made explicit. Earlier ranges win where the input ranges overlap, like in `src_to_dst`.
+    fn normalized(&self) -> Vec<Range> {
+        let mut covered = IntervalSet::new();
+        let mut pieces = Vec::new();
+        for r in &self.ranges {
+            let new_sources = IntervalSet::from(r.src).difference(&covered);
+            pieces.extend(new_sources.iter().map(|s| Range { src: *s, offset: r.offset }));
+            covered = covered.union(&new_sources);
+        }
+        let gaps = IntervalSet::from(Interval::new(i64::MIN, i64::MAX)).difference(&covered);
+        pieces.extend(gaps.iter().map(|g| Range { src: *g, offset: 0 }));
+        pieces.sort_by_key(|p| p.src.start());
+        // neighbours shifting by the same amount are a single linear piece
+        let mut merged: Vec<Range> = Vec::with_capacity(pieces.len());
+        for piece in pieces {
+            match merged.last_mut() {
+                Some(last) if last.offset == piece.offset && last.src.end() == piece.src.start() => {
+                    last.src = Interval::new(last.src.start(), piece.src.end());
+                }
+                _ => merged.push(piece),
+            }
+        }
+        merged
+    }
+
+    /// The map doing `self` followed by `next`, as a single normalized list of ranges.
+    fn compose(&self, next: &RangeMap) -> RangeMap {
+        let next_pieces = next.normalized();
+        let mut ranges = Vec::new();
+        for piece in self.normalized() {
+            let image = piece.dst();
+            // pieces of `next` overlapping the image, which is contiguous, form a contiguous run
+            let first = next_pieces.partition_point(|q| q.src.end() <= image.start());
+            for q in next_pieces[first..].iter().take_while(|q| q.src.start() < image.end()) {
+                let src = image.intersection(&q.src).shift(-piece.offset);
+                ranges.push(Range { src, offset: piece.offset + q.offset });
+            }
+        }
+        let composed = RangeMap { source: self.source.clone(), destination: next.destination.clone(), ranges };
+        RangeMap { ranges: composed.normalized(), ..composed }
+    }
+
+    /// Smallest value any of `input` maps to. Expects normalized ranges, which it binary searches.
+    fn min_over(&self, input: &IntervalSet) -> Option<i64> {
+        input.iter().flat_map(|interval| {
+            let first = self.ranges.partition_point(|r| r.src.end() <= interval.start());
+            self.ranges[first..]
+                .iter()
+                .take_while(|r| r.src.start() < interval.end())
+                .map(|r| r.src.intersection(interval).start() + r.offset)
+        }).min()
+    }
+}
 
-    let seed_locations = seeds.iter().map(|(seed, range)| {
-        let mut ranges = HashSet::from([(*seed, *range)]);
-        for c in &categories {
-            ranges = ranges.iter().map(|(s, r)| c.src_to_dst(*s, *r)).flatten().collect();
+/// Orders the maps by following their category names from `seed` to `location`.
+fn chain_categories(mut maps: Vec<(usize, RangeMap)>) -> Result<Vec<RangeMap>, ParseError> {
+    let mut chain = Vec::new();
+    let mut category = "seed".to_string();
+    // line that introduced the current category, to point at when nothing converts it
+    let mut category_line = 1;
+    while category != "location" {
+        let matching = maps.iter().filter(|(_, m)| m.source == category).map(|(line, _)| *line).collect::<Vec<_>>();
+        match matching[..] {
+            [] => return Err(ParseError::new(category_line, format!("no map converts {} into another category", category))),
+            [_] => {}
+            [_, second, ..] => return Err(ParseError::new(second, format!("more than one map converts {}", category))),
         }
-        ranges
-    }).flatten();
+        let index = maps.iter().position(|(_, m)| m.source == category).unwrap();
+        let (line, map) = maps.remove(index);
+        category = map.destination.clone();
+        category_line = line;
+        chain.push(map);
+    }
+    match maps.first() {
+        Some((line, m)) => Err(ParseError::new(*line, format!("{}-to-{} map is not on the way from seed to location", m.source, m.destination))),
+        None => Ok(chain),
+    }
+}
+
+
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, ParseError> {
+    let data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
+    let categories_blocks = blocks(&data);
+    let (seeds_line, seeds_block) = *categories_blocks.first().ok_or(ParseError::new(1, "missing seeds"))?;
+
+    let seeds = parse_seeds(seeds_block, seeds_line, advanced)?;
+    let categories = categories_blocks[1..]
+        .iter()
+        .map(|(line, c)| Ok((*line, parse_map(c, *line)?)))
+        .collect::<Result<Vec<_>, ParseError>>()?;
+    let categories = chain_categories(categories)?;
+
+    let seed_to_location = categories.iter().fold(RangeMap::identity("seed"), |map, c| map.compose(c));
+    let smallest_location = seed_to_location.min_over(&seeds).ok_or(ParseError::new(seeds_line, "no seeds"))?;
+    if options.explain {
+        explain(&seeds, &categories, &seed_to_location, smallest_location);
+    }
+    Ok(format!("{}", smallest_location))
+}
 
-    let smallest_location: u64 = seed_locations.map(|(seed, _)| seed).min().unwrap();
-    format!("{}", smallest_location)
+/// Prints the category values of a seed reaching `location`, the lowest one, and all seeds reaching it.
+/// The seeds come from `seed_to_location`, the map the location was found with.
+fn explain(seeds: &IntervalSet, categories: &[RangeMap], seed_to_location: &RangeMap, location: i64) {
+    let point = IntervalSet::from(Interval::with_length(location, 1));
+    let sources = seed_to_location.dst_to_src(&point).intersection(seeds);
+    let Some(seed) = sources.iter().next().map(|s| s.start()) else {
+        return;
+    };
+
+    let lineage = categories.iter().fold(vec![Lineage::new(Interval::with_length(seed, 1))], |l, c| c.trace(&l));
+    let names = categories.first().map(|c| c.source.as_str()).into_iter()
+        .chain(categories.iter().map(|c| c.destination.as_str()));
+    println!("{}", names.zip(&lineage[0].offsets).map(|(name, offset)| format!("{} {}", name, seed + offset)).join(" -> "));
+    println!("Seeds reaching location {}: {:?}", location, sources);
 }
 
-fn parse_seeds(input: &str, advanced: bool) -> Vec<(u64, u64)> {
-    let seed_numbers: Vec<u64> = input.split(":").skip(1).next().unwrap().split(" ").filter(|n| n.len() > 0).map(|n| n.parse::<u64>().unwrap()).collect();
+fn parse_seeds(input: &str, line: usize, advanced: bool) -> Result<IntervalSet, ParseError> {
+    // example: seeds: 79 14 55 13
+    let (_, seed_numbers) = parse_line(line, input, key_value(tag("seeds"), unsigned_list::<i64>))?;
     if advanced {
-        seed_numbers.chunks(2).map(|n| (n[0], n[1])).collect()
+        if seed_numbers.len() % 2 != 0 {
+            return Err(ParseError::new(line, "seed ranges must come in pairs of start and length"));
+        }
+        Ok(seed_numbers.chunks(2).map(|n| Interval::with_length(n[0], n[1])).collect())
     } else {
-        seed_numbers.iter().map(|n| (*n, 1)).collect()
+        Ok(seed_numbers.iter().map(|n| Interval::with_length(*n, 1)).collect())
     }
 }
 
-fn parse_map(input: &str) -> RangeMap {
-    let mut ranges = Vec::new();
-    for line in input.lines().skip(1) {
-        let parts = line.split(" ").collect::<Vec<&str>>();
-        let numbers = parts.iter().map(|p| p.parse::<u64>().unwrap()).collect::<Vec<u64>>();
-        ranges.push(Range {
-            dst_start: numbers[0],
-            src_start: numbers[1],
-            length: numbers[2],
+fn parse_map(input: &str, first_line: usize) -> Result<RangeMap, ParseError> {
+    // example: seed-to-soil map:
+    //          50 98 2
+    let (header, range_lines) = input.split_once('\n').unwrap_or((input, ""));
+    let (source, destination) = parse_line(first_line, header, terminated(separated_pair(alpha1, tag("-to-"), alpha1), tag(" map:")))?;
+
+    let range_line = tuple((unsigned::<i64>, preceded(space1, unsigned::<i64>), preceded(space1, unsigned::<i64>)));
+    let ranges = parse_lines(range_lines, first_line + 1, range_line)?
+        .into_iter()
+        .map(|(dst_start, src_start, length)| Range {
+            src: Interval::with_length(src_start, length),
+            offset: dst_start - src_start,
         })
-    }
+        .collect();
 
-    RangeMap {
+    Ok(RangeMap {
+        source: source.to_string(),
+        destination: destination.to_string(),
         ranges,
-    }
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::solutions::day05::{RangeMap};
+    use crate::common::interval::{Interval, IntervalSet};
+    use crate::solutions::day05::{Lineage, RangeMap};
+
+    impl RangeMap {
+        /// Maps a single value. Expects normalized ranges, which it binary searches.
+        fn lookup(&self, value: i64) -> i64 {
+            let index = self.ranges.partition_point(|r| r.src.end() <= value);
+            match self.ranges.get(index) {
+                Some(r) if r.src.contains(value) => value + r.offset,
+                _ => value,
+            }
+        }
+
+        /// `lookup` for maps that are not normalized.
+        fn lookup_slow(&self, value: i64) -> i64 {
+            self.ranges.iter().find(|r| r.src.contains(value)).map_or(value, |r| value + r.offset)
+        }
+    }
+
+    fn set(ranges: &[(i64, i64)]) -> IntervalSet {
+        ranges.iter().map(|(s, l)| Interval::with_length(*s, *l)).collect()
+    }
+
+    /// Everything `input` maps to, read off the normalized pieces.
+    fn image(map: &RangeMap, input: &IntervalSet) -> IntervalSet {
+        map.normalized()
+            .iter()
+            .map(|p| input.intersection(&IntervalSet::from(p.src)).shift(p.offset))
+            .fold(IntervalSet::new(), |acc, s| acc.union(&s))
+    }
 
     #[test]
     fn test_range_src_to_dst() {
         let r = super::Range {
-            dst_start: 30,
-            src_start: 10,
-            length: 6,
+            src: Interval::with_length(10, 6),
+            offset: 20,
         };
 
         // Test the most basic cases
-        assert_eq!(r.src_to_dst(10, 1, false), vec![(30, 1, true)]);
-        assert_eq!(r.src_to_dst(10, 1, true), vec![(10, 1, true)]);
-        assert_eq!(r.src_to_dst(5, 1, false), vec![(5, 1, false)]);
+        assert_eq!(r.src_to_dst(&set(&[(10, 1)])), (set(&[(30, 1)]), set(&[])));
+        assert_eq!(r.src_to_dst(&set(&[(5, 1)])), (set(&[]), set(&[(5, 1)])));
 
         // Test the inside case
-        assert_eq!(r.src_to_dst(12, 2, false), vec![(32, 2, true)]);
+        assert_eq!(r.src_to_dst(&set(&[(12, 2)])), (set(&[(32, 2)]), set(&[])));
 
         // Test the outside case
-        assert_eq!(r.src_to_dst(8, 20, false), vec![(8, 2, false), (30, 6, true), (16, 12, false)])
+        assert_eq!(r.src_to_dst(&set(&[(8, 20)])), (set(&[(30, 6)]), set(&[(8, 2), (16, 12)])));
     }
 
     #[test]
-    fn test_range_map_src_to_dst() {
+    fn test_range_map_image() {
         let r = RangeMap {
+            source: "seed".to_string(),
+            destination: "soil".to_string(),
             ranges: vec![
                 super::Range {
-                    dst_start: 110,
-                    src_start: 10,
-                    length: 6,
+                    src: Interval::with_length(10, 6),
+                    offset: 100,
                 },
                 super::Range {
-                    dst_start: 130,
-                    src_start: 30,
-                    length: 2,
+                    src: Interval::with_length(30, 2),
+                    offset: 100,
                 }
             ]
         };
 
         // Test the inside case
-        assert_eq!(r.src_to_dst(12, 2), vec![(112, 2)]);
+        assert_eq!(image(&r, &set(&[(12, 2)])), set(&[(112, 2)]));
 
         // Test the outside case
-        assert_eq!(r.src_to_dst(8, 20), vec![(8, 2), (110, 6), (16, 12)]);
+        assert_eq!(image(&r, &set(&[(8, 20)])), set(&[(8, 2), (110, 6), (16, 12)]));
 
         // Test overlapping
-        assert_eq!(r.src_to_dst(5, 30), vec![(5, 5), (110, 6), (16, 14), (130, 2), (32, 3)]);
+        assert_eq!(image(&r, &set(&[(5, 30)])), set(&[(5, 5), (110, 6), (16, 14), (130, 2), (32, 3)]));
     }
 
     #[test]
     fn test_problematic_range_map() {
         let r = RangeMap {
+            source: "seed".to_string(),
+            destination: "soil".to_string(),
+            ranges: vec![
+                super::Range {
+                    src: Interval::with_length(53, 8),
+                    offset: -4,
+                },
+            ]
+        };
+
+        assert_eq!(image(&r, &set(&[(57, 13)])), set(&[(53, 4), (61, 9)]));
+    }
+
+    #[test]
+    fn test_trace_and_reverse_lookup() {
+        let r = RangeMap {
+            source: "seed".to_string(),
+            destination: "soil".to_string(),
             ranges: vec![
                 super::Range {
-                    src_start: 53,
-                    dst_start: 49,
-                    length: 8,
+                    src: Interval::with_length(10, 6),
+                    offset: 100,
                 },
             ]
         };
 
-        assert_eq!(r.src_to_dst(57, 13), vec![(53, 4), (61, 9)]);
+        let traced = r.trace(&[Lineage::new(Interval::with_length(8, 4))]);
+        assert_eq!(traced, vec![
+            Lineage { seeds: Interval::with_length(10, 2), offsets: vec![0, 100] },
+            Lineage { seeds: Interval::with_length(8, 2), offsets: vec![0, 0] },
+        ]);
+
+        // 112 is reached both from 12 and from itself, 9 only from itself, and nothing maps to 12
+        assert_eq!(r.dst_to_src(&set(&[(112, 1)])), set(&[(12, 1), (112, 1)]));
+        assert_eq!(r.dst_to_src(&set(&[(9, 1)])), set(&[(9, 1)]));
+        assert_eq!(r.dst_to_src(&set(&[(12, 1)])), set(&[]));
+    }
+
+    #[test]
+    fn test_compose_and_invert() {
+        let seed_to_soil = RangeMap {
+            source: "seed".to_string(),
+            destination: "soil".to_string(),
+            ranges: vec![super::Range { src: Interval::with_length(10, 6), offset: 100 }],
+        };
+        let soil_to_water = RangeMap {
+            source: "soil".to_string(),
+            destination: "water".to_string(),
+            ranges: vec![
+                super::Range { src: Interval::with_length(112, 10), offset: -112 },
+                super::Range { src: Interval::with_length(0, 10), offset: 112 },
+            ],
+        };
+
+        let seed_to_water = seed_to_soil.compose(&soil_to_water);
+        assert_eq!(seed_to_water.destination, "water");
+        for seed in 0..130 {
+            assert_eq!(seed_to_water.lookup(seed), soil_to_water.lookup_slow(seed_to_soil.lookup_slow(seed)));
+        }
+        assert_eq!(seed_to_water.min_over(&set(&[(13, 20)])), Some(1));
+        // 12 goes through soil 112 and 112 stays soil 112, so both end up as water 0
+        assert_eq!(seed_to_water.dst_to_src(&set(&[(0, 1)])), set(&[(12, 1), (112, 1)]));
+
+        // swapping two blocks is one to one
+        let swap = RangeMap {
+            source: "seed".to_string(),
+            destination: "soil".to_string(),
+            ranges: vec![
+                super::Range { src: Interval::with_length(10, 6), offset: 100 },
+                super::Range { src: Interval::with_length(110, 6), offset: -100 },
+            ],
+        };
+        let swap = swap.compose(&RangeMap::identity("soil"));
+        assert_eq!(swap.dst_to_src(&set(&[(113, 1)])), set(&[(13, 1)]));
+        assert_eq!(swap.dst_to_src(&set(&[(13, 1)])), set(&[(113, 1)]));
+        assert_eq!(swap.dst_to_src(&set(&[(50, 1)])), set(&[(50, 1)]));
+
+        // where ranges overlap the earlier one wins, so 12 maps to 112 and 212 is only reached from itself
+        let overlapping = RangeMap {
+            source: "seed".to_string(),
+            destination: "soil".to_string(),
+            ranges: vec![
+                super::Range { src: Interval::with_length(10, 6), offset: 100 },
+                super::Range { src: Interval::with_length(12, 2), offset: 200 },
+            ],
+        };
+        assert_eq!(overlapping.dst_to_src(&set(&[(212, 1)])), set(&[(212, 1)]));
+        assert_eq!(overlapping.dst_to_src(&set(&[(112, 1)])), set(&[(12, 1), (112, 1)]));
     }
-}
\ No newline at end of file
+}
@@ -1,30 +1,30 @@
+use nom::bytes::complete::tag;
+use crate::common::parsing::{key_value, parse_line, unsigned_list, ParseError};
+
 const EXAMPLE: &str = "Time:      7  15   30
 Distance:  9  40  200";
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+pub fn solve(input_data: Option<String>, advanced: bool) -> Result<String, ParseError> {
     let mut data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
 
     if advanced {
         data = data.replace(" ", "");
     }
 
-    let times = skip_parse(data.clone(), 0, "Time:".to_string());
-    let distances = skip_parse(data.clone(), 1, "Distance:".to_string());
+    let lines = data.lines().collect::<Vec<_>>();
+    let times = skip_parse(&lines, 0, "Time")?;
+    let distances = skip_parse(&lines, 1, "Distance")?;
 
     let races = times.iter().zip(distances.iter());
     let result = races.map(|(t, d)| solve_race(*t, *d)).fold(1u64, |a, b| a * b);
-    format!("{}", result)
+    Ok(format!("{}", result))
 }
 
-fn skip_parse(data: String, skip: usize, replace: String) -> Vec<u64> {
-    data.lines()
-        .skip(skip)
-        .next().unwrap()
-        .replace(&replace, "")
-        .split_whitespace()
-        .filter_map(|i| if i.trim().len() > 0 { Some(i.trim())} else {None})
-        .map(|n| n.parse::<u64>().unwrap())
-        .collect()
+fn skip_parse(lines: &[&str], skip: usize, key: &str) -> Result<Vec<u64>, ParseError> {
+    // example: Time:      7  15   30
+    let line = lines.get(skip).ok_or(ParseError::new(skip + 1, format!("missing {} line", key)))?;
+    let (_, values) = parse_line(skip + 1, line, key_value(tag(key), unsigned_list))?;
+    Ok(values)
 }
 
 fn solve_race(time: u64, min_distance: u64) -> u64 {
@@ -1,4 +1,16 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use itertools::Itertools;
+use num_integer::{ExtendedGcd, Integer};
+use nom::character::complete::one_of;
+use nom::IResult;
+use nom::multi::many1;
+use crate::common::dot::DotGraph;
+use crate::common::parallel::par_map;
+use crate::common::parsing::{blocks, named_pair, parse_line, parse_lines, ParseError};
+use crate::solutions::Options;
 
 const EXAMPLE: &str = "LR
 
@@ -35,10 +47,6 @@ impl Node {
         }
     }
 
-    fn is_dead_node(&self) -> bool {
-        self.name == "XXX"
-    }
-
     fn next(&self, node_map: &HashMap<String, Node>, direction: char) -> String {
         node_map.get(match direction {
             'L' => self.left.as_str(),
@@ -47,59 +55,257 @@ impl Node {
         }).unwrap().name.clone()
     }
 
-    fn find_end(&self, node_map: &HashMap<String, Node>, mut instructions: impl Iterator<Item = char>, advanced: bool) -> (String, u64) {
-        let mut current_node = node_map.get(&self.name).unwrap();
+}
+
+/// Where a ghost stands on end nodes. A ghost's state is its node together with the index of the next
+/// instruction; the walk is periodic from the first state it revisits.
+///
+/// `goals` lists every step at which the ghost is on an end node, up to the end of the first period. Those
+/// before `offset` happen only once, the ones from `offset` on repeat every `length` steps.
+#[derive(Debug, PartialEq, Eq)]
+struct Cycle {
+    offset: u64,
+    length: u64,
+    goals: Vec<u64>,
+}
+
+impl Cycle {
+    fn analyze(node_map: &HashMap<String, Node>, instructions: &[char], start: &Node, advanced: bool) -> Cycle {
+        let mut first_visits = HashMap::<(&str, usize), u64>::new();
+        let mut goals = Vec::new();
+        let mut current_node = start;
         let mut steps = 0u64;
 
-        while !current_node.is_end_node(advanced) {
-            let direction = instructions.next().unwrap();
-            let current_node_str = current_node.next(node_map, direction);
+        loop {
+            let instruction_index = steps as usize % instructions.len();
+            if let Some(&first_visit) = first_visits.get(&(current_node.name.as_str(), instruction_index)) {
+                return Cycle { offset: first_visit, length: steps - first_visit, goals };
+            }
+            first_visits.insert((current_node.name.as_str(), instruction_index), steps);
+            if current_node.is_end_node(advanced) {
+                goals.push(steps);
+            }
+
+            let current_node_str = current_node.next(node_map, instructions[instruction_index]);
             current_node = node_map.get(&current_node_str).unwrap();
             steps += 1;
-            if current_node.is_dead_node() {
-                panic!("Dead node");
-            }
         }
+    }
+
+    fn is_goal(&self, step: u64) -> bool {
+        let step = if step < self.offset { step } else { self.offset + (step - self.offset) % self.length };
+        self.goals.binary_search(&step).is_ok()
+    }
+
+    /// Goals that repeat, as congruences `step ≡ goal (mod length)`.
+    fn periodic_goals(&self) -> impl Iterator<Item = u64> + '_ {
+        self.goals.iter().copied().filter(|g| *g >= self.offset)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NavigationError {
+    Parse(ParseError),
+    NoStartNodes,
+    /// `node` leads to `target`, which is not in the network.
+    UndefinedNode { node: String, target: String },
+    /// The walk from this start node repeats itself without ever reaching an end node.
+    Unreachable(String),
+    NoCommonGoal,
+}
+
+impl fmt::Display for NavigationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NavigationError::Parse(e) => write!(f, "{}", e),
+            NavigationError::NoStartNodes => write!(f, "no start nodes in the network"),
+            NavigationError::UndefinedNode { node, target } => write!(f, "node {} leads to {}, which is not in the network", node, target),
+            NavigationError::Unreachable(start) => write!(f, "no end node is reachable from {}", start),
+            NavigationError::NoCommonGoal => write!(f, "the ghosts never stand on end nodes at the same step"),
+        }
+    }
+}
+
+impl Error for NavigationError {}
 
-        (current_node.name.clone(), steps)
+impl From<ParseError> for NavigationError {
+    fn from(e: ParseError) -> NavigationError {
+        NavigationError::Parse(e)
     }
 }
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, NavigationError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
 
-    let instructions: Vec<char> = data.lines().next().unwrap().chars().collect();
+    let (instructions, nodes) = parse_input(&data)?;
+    if let Some(path) = &options.dot {
+        node_graph(&nodes, advanced).export(path);
+    }
     let node_map = HashMap::<String, Node>::from_iter(
-        data.lines()
-            .skip(2)
-            .map(parse_line)
-            .map(|n| (n.name.clone(), n))
+        nodes.into_iter().map(|n| (n.name.clone(), n))
     );
+    // checked up front, so that walking the network can't run off it
+    let undefined = node_map.values()
+        .sorted_by_key(|n| &n.name)
+        .flat_map(|n| [&n.left, &n.right].map(|target| (n, target)))
+        .find(|(_, target)| !node_map.contains_key(*target));
+    if let Some((node, target)) = undefined {
+        return Err(NavigationError::UndefinedNode { node: node.name.clone(), target: target.clone() });
+    }
 
     let current_nodes: Vec<&Node> = node_map
         .values()
         .filter(|n| n.is_start_node(advanced))
+        .sorted_by_key(|n| &n.name)
         .collect();
-    let closest_ends: Vec<(String, u64)> = current_nodes
-        .iter()
-        .map(|n| n.find_end(&node_map, instructions.clone().into_iter().cycle(), advanced))
-        .collect();
-    let steps = closest_ends
+    if current_nodes.is_empty() {
+        return Err(NavigationError::NoStartNodes);
+    }
+    let cycles: Vec<Cycle> = par_map(&current_nodes, |n| Cycle::analyze(&node_map, &instructions, n, advanced));
+    if let Some((start, _)) = current_nodes.iter().zip(&cycles).find(|(_, c)| c.goals.is_empty()) {
+        return Err(NavigationError::Unreachable(start.name.clone()));
+    }
+    let steps = first_common_goal(&cycles).ok_or(NavigationError::NoCommonGoal)?;
+    Ok(format!("{}", steps))
+}
+
+/// Smallest step at which every ghost stands on an end node.
+fn first_common_goal(cycles: &[Cycle]) -> Option<u64> {
+    // goals before a cycle starts happen once, so they only count if every other ghost agrees on the spot
+    let one_off = cycles
         .iter()
-        .map(|(_, steps)| *steps)
-        .fold(1, |acc, s| num_integer::lcm(acc, s));
-    format!("{}", steps)
+        .flat_map(|c| c.goals.iter().copied().filter(|g| *g < c.offset))
+        .filter(|step| cycles.iter().all(|c| c.is_goal(*step)))
+        .min();
+
+    // otherwise pick one periodic goal per ghost and solve the resulting system of congruences
+    let mut congruences = vec![(0u64, 1u64)];
+    for cycle in cycles {
+        congruences = congruences
+            .iter()
+            .flat_map(|&c| cycle.periodic_goals().filter_map(move |g| crt(c, (g % cycle.length, cycle.length))))
+            .unique()
+            .collect();
+    }
+    let earliest = cycles.iter().map(|c| c.offset).max().unwrap_or(0);
+    let periodic = congruences
+        .into_iter()
+        .map(|(residue, modulus)| {
+            // first step at or after every ghost entered its cycle
+            if residue >= earliest { residue } else { residue + (earliest - residue).div_ceil(modulus) * modulus }
+        })
+        .min();
+
+    one_off.into_iter().chain(periodic).min()
 }
 
-fn parse_line(line: &str) -> Node {
-    let clean_line = line.replace("(", "").replace(")", "");
-    let (name, directions) = clean_line.split_once("=").unwrap();
-    let (left, right) = directions.split_once(",").unwrap();
+/// Generalised Chinese Remainder Theorem for moduli that need not be coprime: merges `x ≡ a.0 (mod a.1)` and
+/// `x ≡ b.0 (mod b.1)` into a single congruence, or `None` if no `x` satisfies both.
+fn crt(a: (u64, u64), b: (u64, u64)) -> Option<(u64, u64)> {
+    let (r1, m1, r2, m2) = (a.0 as i128, a.1 as i128, b.0 as i128, b.1 as i128);
+    let ExtendedGcd { gcd, x, .. } = m1.extended_gcd(&m2);
+    if (r2 - r1) % gcd != 0 {
+        return None;
+    }
+    let modulus = m1 / gcd * m2;
+    // x is the inverse of m1 / gcd modulo m2 / gcd
+    let k = ((r2 - r1) / gcd * x).rem_euclid(m2 / gcd);
+    let residue = (r1 + m1 * k).rem_euclid(modulus);
+    Some((u64::try_from(residue).ok()?, u64::try_from(modulus).ok()?))
+}
 
-    Node {
-        name: name.trim().to_string(),
-        left: left.trim().to_string(),
-        right: right.trim().to_string(),
+/// The network with an edge per direction. Start nodes are green, end nodes red.
+fn node_graph(nodes: &[Node], advanced: bool) -> DotGraph {
+    let mut graph = DotGraph::new();
+    for node in nodes {
+        if node.is_start_node(advanced) {
+            graph.node(&node.name, &[("style", "filled"), ("fillcolor", "palegreen")]);
+        } else if node.is_end_node(advanced) {
+            graph.node(&node.name, &[("style", "filled"), ("fillcolor", "salmon")]);
+        }
+        if node.left == node.right {
+            graph.edge(&node.name, &node.left, &[("label", "L,R")]);
+        } else {
+            graph.edge(&node.name, &node.left, &[("label", "L")]);
+            graph.edge(&node.name, &node.right, &[("label", "R")]);
+        }
     }
+    graph
+}
+
+fn parse_input(data: &str) -> Result<(Vec<char>, Vec<Node>), ParseError> {
+    let input_blocks = blocks(data);
+    let [(instructions_line, instructions_block), (nodes_line, nodes_block)] = input_blocks[..] else {
+        return Err(ParseError::new(1, "expected instructions and nodes separated by a blank line"));
+    };
+
+    let instructions = parse_line(instructions_line, instructions_block, many1(one_of("LR")))?;
+    let nodes = parse_lines(nodes_block, nodes_line, parse_node)?;
+    Ok((instructions, nodes))
 }
 
+fn parse_node(line: &str) -> IResult<&str, Node> {
+    // example: AAA = (BBB, CCC)
+    let (rest, (name, (left, right))) = named_pair(line)?;
+
+    Ok((rest, Node {
+        name: name.to_string(),
+        left: left.to_string(),
+        right: right.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crt() {
+        assert_eq!(crt((2, 3), (3, 5)), Some((8, 15)));
+        assert_eq!(crt((2, 4), (4, 6)), Some((10, 12)));
+        assert_eq!(crt((1, 4), (2, 6)), None);
+    }
+
+    #[test]
+    fn test_cycle_analysis() {
+        let (instructions, nodes) = parse_input(EXAMPLE).unwrap();
+        let node_map = nodes.into_iter().map(|n| (n.name.clone(), n)).collect::<HashMap<_, _>>();
+        let ghost = Cycle::analyze(&node_map, &instructions, &node_map["22A"], true);
+        assert_eq!(ghost, Cycle { offset: 1, length: 6, goals: vec![3, 6] });
+        assert!(ghost.is_goal(9) && ghost.is_goal(12) && !ghost.is_goal(10));
+    }
+
+    #[test]
+    fn test_unreachable_end() {
+        let data = "LR\n\nAAA = (BBB, AAA)\nBBB = (AAA, BBB)\nZZZ = (ZZZ, ZZZ)";
+        let result = solve(Some(data.to_string()), false, &Options::default());
+        assert_eq!(result, Err(NavigationError::Unreachable("AAA".to_string())));
+    }
+
+    #[test]
+    fn test_undefined_node() {
+        let data = "LR\n\nAAA = (BBB, ZZZ)\nZZZ = (ZZZ, ZZZ)";
+        let result = solve(Some(data.to_string()), false, &Options::default());
+        assert_eq!(result, Err(NavigationError::UndefinedNode { node: "AAA".to_string(), target: "BBB".to_string() }));
+    }
+
+    #[test]
+    fn test_goals_off_the_cycle_start() {
+        // ends at steps 0, 2, 4, ... and 2, 5, 8, ...: folding the cycle lengths with LCM would say 6
+        let cycles = [
+            Cycle { offset: 0, length: 2, goals: vec![0] },
+            Cycle { offset: 1, length: 3, goals: vec![2] },
+        ];
+        assert_eq!(first_common_goal(&cycles), Some(2));
+        let cycles = [
+            Cycle { offset: 0, length: 2, goals: vec![1] },
+            Cycle { offset: 0, length: 4, goals: vec![2] },
+        ];
+        assert_eq!(first_common_goal(&cycles), None);
+        let cycles = [
+            Cycle { offset: 3, length: 2, goals: vec![1, 3] },
+            Cycle { offset: 0, length: 1, goals: vec![0] },
+        ];
+        assert_eq!(first_common_goal(&cycles), Some(1));
+    }
+}
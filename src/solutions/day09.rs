@@ -1,21 +1,15 @@
+use crate::common::parsing::{parse_lines, signed_list, ParseError};
+
 const EXAMPLE: &str = "0 3 6 9 12 15
 1 3 6 10 15 21
 10 13 16 21 30 45";
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+pub fn solve(input_data: Option<String>, advanced: bool) -> Result<String, ParseError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
-    let inputs: Vec<Vec<i64>> = data
-        .lines()
-        .map(|l| l
-            .split_whitespace()
-            .map(|n| n
-                .parse::<i64>()
-                .unwrap())
-            .collect::<Vec<i64>>())
-        .collect();
+    let inputs: Vec<Vec<i64>> = parse_lines(&data, 1, signed_list)?;
 
     let result: i64 = inputs.iter().map(|i| extrapolate(i, advanced)).sum();
-    format!("{}", result)
+    Ok(format!("{}", result))
 }
 
 fn extrapolate(series: &Vec<i64>, advanced: bool) -> i64 {
@@ -1,4 +1,12 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use itertools::Itertools;
+use crate::common::geometry::{Location, Polygon};
+use crate::common::parsing::{char_grid, ParseError};
+use crate::common::render::{frame_step, Color, Glyph, Render};
+use crate::solutions::Options;
 
 const EXAMPLE: &str = "...........
 .S-------7.
@@ -10,7 +18,7 @@ const EXAMPLE: &str = "...........
 .L--J.L--J.
 ...........";
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Direction {
     North,
     East,
@@ -19,6 +27,17 @@ enum Direction {
 }
 
 impl Direction {
+    const ALL: [Direction; 4] = [Direction::North, Direction::East, Direction::South, Direction::West];
+
+    fn opposite(&self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+        }
+    }
+
     fn vector(&self) -> (i32, i32) {
         match self {
             Direction::North => (-1, 0),
@@ -31,7 +50,7 @@ impl Direction {
 
 type TileIndex = (usize, usize);
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 enum Tile {
     Starting,
     NS,
@@ -44,23 +63,61 @@ enum Tile {
 }
 
 impl Tile {
-    fn parse(c: char) -> Tile {
+    const PIPES: [Tile; 6] = [Tile::NS, Tile::EW, Tile::NE, Tile::NW, Tile::SW, Tile::SE];
+
+    fn parse(c: char) -> Option<Tile> {
         match c {
-            'S' => Tile::Starting,
-            '|' => Tile::NS,
-            '-' => Tile::EW,
-            'L' => Tile::NE,
-            'J' => Tile::NW,
-            '7' => Tile::SW,
-            'F' => Tile::SE,
-            '.' => Tile::Ground,
-            _ => panic!("Unexpected item in piping area: {}", c),
+            'S' => Some(Tile::Starting),
+            '|' => Some(Tile::NS),
+            '-' => Some(Tile::EW),
+            'L' => Some(Tile::NE),
+            'J' => Some(Tile::NW),
+            '7' => Some(Tile::SW),
+            'F' => Some(Tile::SE),
+            '.' => Some(Tile::Ground),
+            _ => None,
+        }
+    }
+
+    /// The character the tile is written as in the puzzle input.
+    fn character(&self) -> char {
+        match self {
+            Tile::Starting => 'S',
+            Tile::NS => '|',
+            Tile::EW => '-',
+            Tile::NE => 'L',
+            Tile::NW => 'J',
+            Tile::SW => '7',
+            Tile::SE => 'F',
+            Tile::Ground => '.',
         }
     }
 
+    /// The pipe joining `a` and `b`.
+    fn connecting(a: Direction, b: Direction) -> Tile {
+        Tile::PIPES.into_iter()
+            .find(|p| p.valid_neighbors_directions().contains(&a) && p.valid_neighbors_directions().contains(&b))
+            .unwrap()
+    }
+
+    fn symbol(&self) -> char {
+        match self {
+            Tile::Starting => 'S',
+            Tile::NS => '│',
+            Tile::EW => '─',
+            Tile::NE => '└',
+            Tile::NW => '┘',
+            Tile::SW => '┐',
+            Tile::SE => '┌',
+            Tile::Ground => '.',
+        }
+    }
+
+    /// Directions the tile connects to. The start tile hides its pipe, so it connects nowhere until its shape
+    /// has been inferred.
     fn valid_neighbors_directions(&self) -> Vec<Direction> {
         match self {
-            Tile::Starting => vec![Direction::North, Direction::East, Direction::South, Direction::West],
+            Tile::Starting => vec![],
             Tile::NS => vec![Direction::North, Direction::South],
             Tile::EW => vec![Direction::East, Direction::West],
             Tile::NE => vec![Direction::North, Direction::East],
@@ -72,44 +129,109 @@ impl Tile {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
-enum ExpandedTile {
-    Unknown,
-    Path,
-    Outside,
+type Map = Vec<Vec<Tile>>;
+
+#[derive(Debug)]
+pub enum PipeError {
+    Parse(ParseError),
+    NoStart,
+    MultipleStarts,
+    NoLoop,
+    /// Every one of these pipes under the start tile closes a different loop.
+    MultipleLoops(Vec<char>),
+}
+
+impl Display for PipeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PipeError::Parse(e) => write!(f, "{}", e),
+            PipeError::NoStart => write!(f, "there is no start tile S on the map"),
+            PipeError::MultipleStarts => write!(f, "there is more than one start tile S on the map"),
+            PipeError::NoLoop => write!(f, "no loop of pipes runs through the start tile"),
+            PipeError::MultipleLoops(shapes) => {
+                write!(f, "the start tile could be any of {} and close a different loop each time", shapes.iter().join(", "))
+            }
+        }
+    }
+}
+
+impl Error for PipeError {}
+
+impl From<ParseError> for PipeError {
+    fn from(e: ParseError) -> PipeError {
+        PipeError::Parse(e)
+    }
 }
 
-type Map = Vec<Vec<Tile>>;
-type ExpandedMap = Vec<Vec<ExpandedTile>>;
+/// The map with the (possibly partial) loop drawn on top. Once the loop is complete, the remaining tiles are
+/// coloured by whether they lie inside or outside of it.
+struct LoopView<'a> {
+    map: &'a Map,
+    path: &'a [TileIndex],
+    polygon: Option<Polygon>,
+}
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+impl Render for LoopView<'_> {
+    fn glyphs(&self) -> Vec<Vec<Glyph>> {
+        let path_tiles = self.path.iter().collect::<HashSet<_>>();
+        self.map.iter().enumerate().map(|(y, row)| {
+            row.iter().enumerate().map(|(x, tile)| {
+                if path_tiles.contains(&(y, x)) {
+                    let color = if self.path.first() == Some(&(y, x)) { Color::Magenta } else { Color::Yellow };
+                    return Glyph::new(tile.symbol(), color);
+                }
+                match self.polygon.as_ref().map(|p| p.locate((y as i64, x as i64))) {
+                    Some(Location::Inside) => Glyph::new('█', Color::Green),
+                    Some(_) => Glyph::new('~', Color::Blue),
+                    None => Glyph::new(tile.symbol(), Color::Gray),
+                }
+            }).collect()
+        }).collect()
+    }
+}
+
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, PipeError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).to_string();
-    let map: Map = data.trim().lines().map(|line| line.chars().map(Tile::parse).collect()).collect();
-    let starting_position = find_start(&map).unwrap();
-    let loop_path = find_loop_iter(&map, starting_position).unwrap();
+    let mut map: Map = char_grid(data.trim(), 1, Tile::parse)?;
+    let starting_position = find_start(&map)?;
+    let (start_tile, loop_path) = find_loop(&map, starting_position)?;
+    map[starting_position.0][starting_position.1] = start_tile;
+    if options.explain {
+        println!("Start tile at row {}, column {} is {}", starting_position.0 + 1, starting_position.1 + 1, start_tile.character());
+    }
+    // every tile of the loop is a lattice point on the polygon boundary
+    let loop_polygon = Polygon::new(loop_path.iter().map(|(y, x)| (*y as i64, *x as i64)).collect());
+
+    if let Some(visualizer) = &options.visualizer {
+        for end in (1..loop_path.len()).step_by(frame_step(loop_path.len())) {
+            visualizer.frame(&LoopView { map: &map, path: &loop_path[..end], polygon: None });
+        }
+        visualizer.show(&LoopView { map: &map, path: &loop_path, polygon: Some(loop_polygon.simplified()) });
+    }
+    if let Some(exporter) = &options.exporter {
+        for end in (1..loop_path.len()).step_by(frame_step(loop_path.len())) {
+            exporter.frame(&LoopView { map: &map, path: &loop_path[..end], polygon: None });
+        }
+        exporter.export(&LoopView { map: &map, path: &loop_path, polygon: Some(loop_polygon.simplified()) });
+    }
 
     if advanced {
-        let mut expanded_map = expanded_map_with_path(&map, &loop_path);
-        flood_outside(&mut expanded_map);
-        let shrunk_map = shrink_map(expanded_map);
-        // assume that everything not flooded or in path is inside
-        let inside_tiles: usize = shrunk_map.iter().map(|r| r.iter().filter(|t| **t == ExpandedTile::Unknown).count()).sum();
-        format!("{}", inside_tiles)
+        Ok(format!("{}", loop_polygon.interior_points()))
     } else {
         let max_distance = ((loop_path.len() - 1) as f64 / 2.0).ceil() as u32;
-        format!("{}", max_distance)
+        Ok(format!("{}", max_distance))
     }
 }
 
-fn find_start(map: &Map) -> Option<TileIndex> {
-    for (y, row) in map.iter().enumerate() {
-        for (x, tile) in row.iter().enumerate() {
-            if *tile == Tile::Starting {
-                return Some((y, x));
-            }
-        }
+fn find_start(map: &Map) -> Result<TileIndex, PipeError> {
+    let mut starts = map.iter().enumerate().flat_map(|(y, row)| {
+        row.iter().enumerate().filter(|(_, t)| **t == Tile::Starting).map(move |(x, _)| (y, x))
+    });
+    match (starts.next(), starts.next()) {
+        (None, _) => Err(PipeError::NoStart),
+        (Some(start), None) => Ok(start),
+        (Some(_), Some(_)) => Err(PipeError::MultipleStarts),
     }
-    return None;
 }
 
 fn map_get_tile(map: &Map, tile_index: TileIndex) -> Tile {
@@ -127,146 +249,69 @@ fn map_tile_with_vector(map: &Map, tile_index: TileIndex, vector: (i32, i32)) ->
     Some((new_tile_index.0 as usize, new_tile_index.1 as usize))
 }
 
-fn find_loop_iter(map: &Map, starting_tile_index: TileIndex) -> Option<Vec<TileIndex>> {
-    let mut last_tile_index: Option<TileIndex> = None;
-    let mut visited_tiles = HashSet::<TileIndex>::new();
-    let mut queue = VecDeque::<(TileIndex, Vec<TileIndex>)>::new();
-    visited_tiles.insert(starting_tile_index);
-    queue.push_back((starting_tile_index, vec![starting_tile_index]));
-
-    while !queue.is_empty() {
-        let (current_tile_index, current_path) = queue.pop_front().unwrap();
-        let current_tile = map_get_tile(map, current_tile_index);
-
-        let neighbor_directions = current_tile.valid_neighbors_directions();
-        let neighbor_vectors: Vec<(i32, i32)> = neighbor_directions.iter().map(|d| d.vector()).collect();
-        let neighbor_indices: Vec<TileIndex> = neighbor_vectors
-            .iter()
-            .map(|v| map_tile_with_vector(map, current_tile_index, *v)).
-            filter_map(|i| i)
-            .collect();
-
-        // Invalid connection
-        if last_tile_index.is_some() && !neighbor_indices.contains(&last_tile_index?) {
-            continue;
-        }
-
-        // Are we finished?
-        if current_path.len() > 2 && neighbor_indices.iter().any(|n| starting_tile_index == *n) {
-            let whole_path = current_path.iter().chain(vec![starting_tile_index].iter()).map(|t| *t).collect();
-            return Some(whole_path);
-        }
-
-        // If not, filter out all other already visited tiles
-        let unvisited_neighbors: Vec<(usize, usize)> = neighbor_indices
-            .iter()
-            .filter(|i| !visited_tiles.contains(*i))
-            .map(|i| *i)
-            .collect();
-
-        // At this point we only have valid neighbors
-        visited_tiles.insert(current_tile_index);
-        for neighbor_index in unvisited_neighbors {
-            let new_path = current_path.iter().chain(vec![neighbor_index].iter()).map(|t| *t).collect();
-            queue.push_back((neighbor_index, new_path));
-        }
-
-        last_tile_index = Some(current_tile_index);
+/// Works out which pipe hides under the start tile. Every pipe joining two neighbours that connect back is
+/// tried, in a fixed order, and it has to be the only one that closes a loop. Returns the pipe and the loop,
+/// which begins and ends on the start tile.
+fn find_loop(map: &Map, start: TileIndex) -> Result<(Tile, Vec<TileIndex>), PipeError> {
+    let connected = Direction::ALL.into_iter().filter(|d| {
+        map_tile_with_vector(map, start, d.vector())
+            .is_some_and(|n| map_get_tile(map, n).valid_neighbors_directions().contains(&d.opposite()))
+    }).collect::<Vec<_>>();
+    let mut loops = connected.iter()
+        .tuple_combinations()
+        .map(|(a, b)| Tile::connecting(*a, *b))
+        .filter_map(|tile| follow_loop(map, start, tile).map(|path| (tile, path)))
+        .collect::<Vec<_>>();
+    match loops.len() {
+        0 => Err(PipeError::NoLoop),
+        1 => Ok(loops.remove(0)),
+        _ => Err(PipeError::MultipleLoops(loops.iter().map(|(t, _)| t.character()).collect())),
     }
-
-    return None;
 }
 
-fn expanded_map_with_path(map: &Map, path: &Vec<TileIndex>) -> ExpandedMap {
-    let row_length = map[0].len() * 2 + 1;
-    let column_length = map.len() * 2 + 1;
-    let mut empty_map = vec![vec![ExpandedTile::Unknown; row_length]; column_length];
-    draw_expanded_path(&mut empty_map, path);
-    return empty_map;
-}
-
-fn draw_expanded_path(map: &mut ExpandedMap, path: &Vec<TileIndex>) {
-    for (tile_index, next_tile_index) in path.iter().zip(path.iter().skip(1)) {
-        let expanded_tile_index = (tile_index.0 * 2 + 1, tile_index.1 * 2 + 1);
-        let expanded_next_tile_index = (next_tile_index.0 * 2 + 1, next_tile_index.1 * 2 + 1);
-
-        let middle_diff = (
-            (expanded_next_tile_index.0 as i32 - expanded_tile_index.0 as i32) / 2,
-            (expanded_next_tile_index.1 as i32 - expanded_tile_index.1 as i32) / 2,
-        );
-        let middle = (
-            (expanded_tile_index.0 as i32 + middle_diff.0) as usize,
-            (expanded_tile_index.1 as i32 + middle_diff.1) as usize
-        );
-
-        map[expanded_tile_index.0][expanded_tile_index.1] = ExpandedTile::Path;
-        map[middle.0][middle.1] = ExpandedTile::Path;
-    }
-}
-
-fn flood_outside(map: &mut ExpandedMap) {
-    let mut visited_tiles = HashSet::<TileIndex>::new();
-    let mut queue = VecDeque::<TileIndex>::new();
-
-    let row_length = map[0].len();
-    let column_length = map.len();
-    // for each unknown tile on the edge, add to queue
-    for y in 0..map.len() {
-        if map[y][0] == ExpandedTile::Unknown {
-            queue.push_back((y, 0));
-            visited_tiles.insert((y, 0));
+/// Follows the pipes from the start tile, assuming it is `start_tile`, until they lead back to it. `None` if
+/// they break off first, or come back to it from a side `start_tile` doesn't connect to.
+fn follow_loop(map: &Map, start: TileIndex, start_tile: Tile) -> Option<Vec<TileIndex>> {
+    let start_directions = start_tile.valid_neighbors_directions();
+    let mut path = vec![start];
+    let mut current = start;
+    let mut heading = start_directions[0];
+    loop {
+        current = map_tile_with_vector(map, current, heading.vector())?;
+        path.push(current);
+        if current == start {
+            return start_directions.contains(&heading.opposite()).then_some(path);
         }
-        if map[y][row_length - 1] == ExpandedTile::Unknown {
-            queue.push_back((y, row_length - 1));
-            visited_tiles.insert((y, row_length - 1));
-        }
-    }
-    for x in 0..map[0].len() {
-        if map[0][x] == ExpandedTile::Unknown {
-            queue.push_back((0, x));
-            visited_tiles.insert((0, x));
-        }
-        if map[column_length - 1][x] == ExpandedTile::Unknown {
-            queue.push_back((column_length - 1, x));
-            visited_tiles.insert((column_length - 1, x));
-        }
-    }
-
-    while !queue.is_empty() {
-        let current_tile_index = queue.pop_front().unwrap();
-        map[current_tile_index.0][current_tile_index.1] = ExpandedTile::Outside;
-
-        for n_diff in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
-            let neighbor_index = (current_tile_index.0 as i32 + n_diff.0, current_tile_index.1 as i32 + n_diff.1);
-
-            if neighbor_index.0 < 0 || neighbor_index.1 < 0 {
-                continue;
-            }
-            if neighbor_index.0 >= column_length as i32 || neighbor_index.1 >= row_length as i32 {
-                continue;
-            }
-
-            let neighbor_index_usize = (neighbor_index.0 as usize, neighbor_index.1 as usize);
-            if visited_tiles.contains(&neighbor_index_usize) {
-                continue;
-            }
-
-            if map[neighbor_index_usize.0][neighbor_index_usize.1] == ExpandedTile::Unknown {
-                queue.push_back(neighbor_index_usize);
-                visited_tiles.insert(neighbor_index_usize);
-            }
+        let directions = map_get_tile(map, current).valid_neighbors_directions();
+        if !directions.contains(&heading.opposite()) {
+            return None;
         }
+        heading = *directions.iter().find(|d| **d != heading.opposite())?;
     }
 }
 
-fn shrink_map(map: ExpandedMap) -> ExpandedMap {
-    let row_length = (map[0].len() - 1) / 2;
-    let column_length = (map.len() - 1) / 2;
-    let mut shrunk_map = vec![vec![ExpandedTile::Unknown; row_length]; column_length];
-    for y in 0..shrunk_map.len() {
-        for x in 0..shrunk_map[y].len() {
-            shrunk_map[y][x] = map[2 * y + 1][2 * x + 1]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_loop() {
+        let map = char_grid("-L|F7\n7S-7|\nL|7||\n-L-J|\nL|-JF", 1, Tile::parse).unwrap();
+        let (tile, path) = find_loop(&map, find_start(&map).unwrap()).unwrap();
+        assert_eq!(tile, Tile::SE);
+        assert_eq!(path.len(), 9);
+        assert_eq!((path[0], path[8]), ((1, 1), (1, 1)));
+
+        // the start could close either of two loops
+        let map = char_grid("F-7..\n|.|..\nL-S-7\n..|.|\n..L-J", 1, Tile::parse).unwrap();
+        match find_loop(&map, (2, 2)) {
+            Err(PipeError::MultipleLoops(shapes)) => assert_eq!(shapes, vec!['J', 'F']),
+            other => panic!("expected two loops, got {:?}", other),
         }
+
+        let map = char_grid("S-7\n|.|\nL-.", 1, Tile::parse).unwrap();
+        assert!(matches!(find_loop(&map, (0, 0)), Err(PipeError::NoLoop)));
+        assert!(matches!(solve(Some("S-7\n|X|".to_string()), false, &Options::default()), Err(PipeError::Parse(_))));
+        assert!(matches!(solve(Some("F-7\n|.|".to_string()), false, &Options::default()), Err(PipeError::NoStart)));
     }
-    return shrunk_map;
-}
\ No newline at end of file
+}
@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::iter::repeat;
 use itertools::Itertools;
+use crate::common::parallel::par_map;
+use crate::solutions::{OptionError, Options};
 
 const EXAMPLE: &str = "???.### 1,1,3
 .??..??...?##. 1,1,3
@@ -18,13 +22,68 @@ enum Spring {
 
 type Group = u32;
 type Groups = Vec<Group>;
+/// Arrangement counts grow exponentially with the unfold factor, so they get the widest native integer.
+type Count = u128;
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+#[derive(Debug)]
+pub enum ArrangementError {
+    Option(OptionError),
+    TooManyArrangements,
+}
+
+impl Display for ArrangementError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ArrangementError::Option(e) => write!(f, "{}", e),
+            ArrangementError::TooManyArrangements => write!(f, "there are too many arrangements to count"),
+        }
+    }
+}
+
+impl Error for ArrangementError {}
+
+impl From<OptionError> for ArrangementError {
+    fn from(e: OptionError) -> ArrangementError {
+        ArrangementError::Option(e)
+    }
+}
+
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, ArrangementError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
-    let repeats = if advanced {5} else {1};
+    let repeats = options.fold.unwrap_or(if advanced {5} else {1});
+    if repeats == 0 {
+        return Err(OptionError::new("fold", repeats, "every row needs at least one copy").into());
+    }
     let spring_lines = data.lines().map(|l| parse_line(l, repeats)).collect::<Vec<_>>();
-    let valid_arrangement_counts = spring_lines.iter().map(|(s, g)| count_arrangements_rec(&mut HashMap::new(), s, g)).collect::<Vec<_>>();
-    format!("{}", valid_arrangement_counts.iter().sum::<u64>())
+    let valid_arrangement_counts = par_map(&spring_lines, |(s, g)| count_arrangements(s, g));
+    let total = valid_arrangement_counts.into_iter()
+        .try_fold(0 as Count, |total, count| total.checked_add(count?))
+        .ok_or(ArrangementError::TooManyArrangements)?;
+    if options.arrangements.is_some() || options.sample {
+        show_arrangements(&spring_lines, options);
+    }
+    Ok(format!("{}", total))
+}
+
+/// Prints the first arrangements and a random one for every row, as asked for in `options`. The rows have to
+/// have been counted without overflowing.
+fn show_arrangements(spring_lines: &[(Vec<Spring>, Groups)], options: &Options) {
+    for (springs, groups) in spring_lines {
+        let table = ArrangementTable::new(springs, groups).expect("the row has been counted");
+        let pattern = springs.iter().map(|s| match s {
+            Spring::Unknown => '?',
+            Spring::Faulty => '#',
+            Spring::Working => '.',
+        }).collect::<String>();
+        println!("{} {} ({} arrangements)", pattern, groups.iter().join(","), table.ways(0, 0));
+        for arrangement in table.arrangements().take(options.arrangements.unwrap_or(0)) {
+            println!("  {}", arrangement);
+        }
+        if options.sample {
+            let sample = table.sample(|n| fastrand::u128(0..n));
+            println!("  random: {}", sample.as_deref().unwrap_or("none"));
+        }
+    }
 }
 
 fn parse_line(line: &str, repeats: usize) -> (Vec<Spring>, Groups) {
@@ -42,91 +101,134 @@ fn parse_line(line: &str, repeats: usize) -> (Vec<Spring>, Groups) {
     (springs, groups)
 }
 
-fn count_arrangements_rec(cache: &mut HashMap<String, u64>, springs: &Vec<Spring>, expected_arrangement: &Groups) -> u64 {
-    let cache_key = springs_cache_key(springs, expected_arrangement);
-    if cache.contains_key(&cache_key.clone()) {
-        return  *cache.get(&cache_key.clone()).unwrap();
-    }
-
-    if (springs.len() == 0 || springs.into_iter().all(|s| *s != Spring::Faulty)) && expected_arrangement.len() == 0 {
-        cache.insert(cache_key, 1);
-        return 1;
-    }
-
-    if springs.len() == 0 || expected_arrangement.len() == 0 {
-        cache.insert(cache_key, 0);
-        return 0;
-    }
+/// Number of ways to fill in the unknown springs so that the faulty ones form exactly `groups`, or `None` if
+/// there are more than a `Count` can hold.
+fn count_arrangements(springs: &[Spring], groups: &[Group]) -> Option<Count> {
+    Some(ArrangementTable::new(springs, groups)?.ways(0, 0))
+}
 
-    let first_spring = springs[0];
-    return match first_spring {
-        Spring::Working => count_arrangements_rec(cache, &springs[1..].to_vec(), expected_arrangement),
-        Spring::Faulty => {
-            // check whether the amount of faulty springs matches the expected arrangement
-            let first_group_length = expected_arrangement[0] as usize;
-            if springs.len() < first_group_length {
-                cache.insert(cache_key, 0);
-                return 0;
-            }
+/// Bottom-up DP where `ways[i][g]` counts the arrangements of `springs[i..]` matching `groups[g..]`. The
+/// table is the only allocation, so long unfolded rows stay cheap.
+struct ArrangementTable<'a> {
+    springs: &'a [Spring],
+    groups: &'a [Group],
+    ways: Vec<Vec<Count>>,
+}
 
-            if springs[0..first_group_length].iter().any(|s| *s == Spring::Working) {
-                cache.insert(cache_key, 0);
-                return 0; // we don't have enough faulty springs
-            }
+impl<'a> ArrangementTable<'a> {
+    /// Fills in the table, or returns `None` if some count doesn't fit in a `Count`.
+    fn new(springs: &'a [Spring], groups: &'a [Group]) -> Option<ArrangementTable<'a>> {
+        let n = springs.len();
+        let m = groups.len();
+        // broken_run[i] is how many springs from i on could all be faulty
+        let mut broken_run = vec![0usize; n + 1];
+        for i in (0..n).rev() {
+            broken_run[i] = if springs[i] == Spring::Working { 0 } else { broken_run[i + 1] + 1 };
+        }
 
-            if springs.len() > first_group_length {
-                if springs[first_group_length] == Spring::Faulty {
-                    // we need series to be split by working springs
-                    cache.insert(cache_key, 0);
-                    return 0;
+        let mut ways = vec![vec![0 as Count; m + 1]; n + 2];
+        ways[n][m] = 1;
+        ways[n + 1][m] = 1;
+        for i in (0..n).rev() {
+            for g in 0..=m {
+                let mut count = 0;
+                if springs[i] != Spring::Faulty {
+                    count = ways[i + 1][g];
                 }
-            }
-
-            // we have at least enough faulty springs. Check that they are followed by a working spring
-            if expected_arrangement.len() > 1 {
-                if springs.len() < first_group_length + 1 {
-                    cache.insert(cache_key, 0);
-                    return 0; // there aren't enough numbers left
+                if g < m {
+                    let length = groups[g] as usize;
+                    // a group starting here has to fit and must not run into another faulty spring
+                    let fits = broken_run[i] >= length && springs.get(i + length) != Some(&Spring::Faulty);
+                    if length > 0 && fits {
+                        count = count.checked_add(ways[i + length + 1][g + 1])?;
+                    }
                 }
+                ways[i][g] = count;
             }
+        }
+        Some(ArrangementTable { springs, groups, ways })
+    }
 
+    fn ways(&self, position: usize, group: usize) -> Count {
+        self.ways[position][group]
+    }
 
+    /// Arrangements continuing from `(position, group)` with a working spring, and with the next group
+    /// starting right here. The two add up to `ways(position, group)`.
+    fn split_ways(&self, position: usize, group: usize) -> (Count, Count) {
+        let working = match self.springs[position] {
+            Spring::Faulty => 0,
+            _ => self.ways(position + 1, group),
+        };
+        (working, self.ways(position, group) - working)
+    }
 
-            // here, we know that the first group is correct.
-            let rest_springs = if springs.len() < first_group_length + 1 {
-                vec![]
-            } else {
-                springs[(first_group_length+1)..].to_vec()
-            };
-            let rest_arrangements = if expected_arrangement.len() == 0 {
-                vec![]
-            } else {
-                expected_arrangement[1..].to_vec()
-            };
+    /// Places the next group at `position`. Returns the springs written, separator included, and the position
+    /// after them.
+    fn place_group(&self, position: usize, group: usize) -> (String, usize) {
+        let length = self.groups[group] as usize;
+        let separator = if position + length < self.springs.len() { "." } else { "" };
+        ("#".repeat(length) + separator, position + length + 1)
+    }
+
+    /// Every valid arrangement, as `.`/`#` strings, generated one at a time.
+    fn arrangements(&'a self) -> Arrangements<'a> {
+        let start = if self.ways(0, 0) > 0 { vec![(0, 0, String::new())] } else { vec![] };
+        Arrangements { table: self, stack: start }
+    }
 
-            let count = count_arrangements_rec(cache, &rest_springs, &rest_arrangements);
-            cache.insert(cache_key, count);
-            return count;
+    /// Picks one of the valid arrangements, each with the same probability. `random_below(n)` has to return
+    /// a uniformly distributed number in `0..n`.
+    fn sample(&self, mut random_below: impl FnMut(Count) -> Count) -> Option<String> {
+        if self.ways(0, 0) == 0 {
+            return None;
         }
-        Spring::Unknown => {
-            // Try both possibilities
-            let count = [Spring::Working, Spring::Faulty].iter().map(|s| {
-                let mut cloned_springs = springs.clone();
-                cloned_springs[0] = *s;
-                count_arrangements_rec(cache, &cloned_springs, expected_arrangement)
-            }).sum();
-            cache.insert(cache_key, count);
-            return count;
+        let mut result = String::new();
+        let (mut position, mut group) = (0, 0);
+        while position < self.springs.len() {
+            // continue with either choice in proportion to the arrangements it leads to
+            let (working, _) = self.split_ways(position, group);
+            if random_below(self.ways(position, group)) < working {
+                result.push('.');
+                position += 1;
+            } else {
+                let (springs, next) = self.place_group(position, group);
+                result.push_str(&springs);
+                position = next;
+                group += 1;
+            }
         }
+        Some(result)
     }
 }
 
-fn springs_cache_key (springs: &Vec<Spring>, expected_arrangement: &Groups) -> String {
-    springs.iter().map(|s| match s {
-        Spring::Unknown => '?',
-        Spring::Faulty => '#',
-        Spring::Working => '.',
-    }).join("") + &expected_arrangement.iter().join(",")
+/// Depth-first walk over the DP table that only enters states with at least one arrangement, so every
+/// branch it takes ends in a result.
+struct Arrangements<'a> {
+    table: &'a ArrangementTable<'a>,
+    stack: Vec<(usize, usize, String)>,
+}
+
+impl Iterator for Arrangements<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while let Some((position, group, prefix)) = self.stack.pop() {
+            if position >= self.table.springs.len() {
+                return Some(prefix);
+            }
+            let (working, faulty) = self.table.split_ways(position, group);
+            // pushed in reverse, so that working springs come first
+            if faulty > 0 {
+                let (springs, next) = self.table.place_group(position, group);
+                self.stack.push((next, group + 1, prefix.clone() + &springs));
+            }
+            if working > 0 {
+                self.stack.push((position + 1, group, prefix + "."));
+            }
+        }
+        None
+    }
 }
 
 #[cfg(test)]
@@ -136,7 +238,48 @@ mod tests {
     #[test]
     fn count_arrangements_test() {
         let line = parse_line("???### 5", 1);
-        let res = count_arrangements_rec(&mut HashMap::new(), &line.0, &line.1);
-        assert_eq!(res, 1);
+        let res = count_arrangements(&line.0, &line.1);
+        assert_eq!(res, Some(1));
+    }
+
+    #[test]
+    fn test_arrangements() {
+        let (springs, groups) = parse_line("?###???????? 3,2,1", 1);
+        let table = ArrangementTable::new(&springs, &groups).unwrap();
+        let arrangements = table.arrangements().collect::<Vec<_>>();
+        assert_eq!(arrangements.len() as Count, table.ways(0, 0));
+        assert_eq!(arrangements[0], ".###....##.#");
+        assert!(arrangements.iter().all(|a| a.len() == springs.len()));
+        assert_eq!(arrangements.iter().unique().count(), arrangements.len());
+
+        // always taking the smallest or largest random number gives the first and last arrangement
+        assert_eq!(table.sample(|_| 0), arrangements.first().cloned());
+        assert_eq!(table.sample(|n| n - 1), arrangements.last().cloned());
+
+        let (springs, groups) = parse_line("#.# 3", 1);
+        let table = ArrangementTable::new(&springs, &groups).unwrap();
+        assert_eq!(table.arrangements().next(), None);
+        assert_eq!(table.sample(|_| 0), None);
+    }
+
+    #[test]
+    fn test_unfolded_example() {
+        let counts = EXAMPLE.lines().map(|l| {
+            let (springs, groups) = parse_line(l, 5);
+            count_arrangements(&springs, &groups).unwrap()
+        }).collect::<Vec<_>>();
+        assert_eq!(counts, vec![1, 16384, 1, 16, 2500, 506250]);
+
+        // 4 ways to place the single group per copy, and any mix of them works as copies are separated
+        let (springs, groups) = parse_line("???? 2", 20);
+        assert!(count_arrangements(&springs, &groups).unwrap() > 3u128.pow(20));
+    }
+
+    #[test]
+    fn test_fold_limits() {
+        let fold = |fold| solve(None, false, &Options { fold: Some(fold), ..Options::default() });
+        assert_eq!(fold(1).unwrap(), "21");
+        assert!(matches!(fold(0), Err(ArrangementError::Option(e)) if e.flag == "fold"));
+        assert!(matches!(fold(40), Err(ArrangementError::TooManyArrangements)));
     }
 }
\ No newline at end of file
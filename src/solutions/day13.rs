@@ -1,4 +1,10 @@
-use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use itertools::Itertools;
+use crate::common::parallel::par_map;
+use crate::common::parsing::{blocks, char_grid, ParseError};
+use crate::solutions::Options;
 
 const EXAMPLE: &str = "#.##..##.
 ..#.##.#.
@@ -18,93 +24,136 @@ const EXAMPLE: &str = "#.##..##.
 
 
 type Block = Vec<Vec<char>>;
+type Cell = (usize, usize);
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
-    let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
-    let blocks = data.split("\n\n").map(parse_block).collect::<Vec<_>>();
-    let mirrors = blocks.iter().map(|b| mirrors_for_block(b, advanced)).collect::<Vec<_>>();
-    let scores = mirrors.iter().map(|(hm, vm)| hm + 100 * vm).collect::<Vec<_>>();
-    let result = scores.iter().sum::<i64>();
-    format!("{}", result)
+/// Line of reflection, given by how many columns are left of it or how many rows are above it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Axis {
+    Columns(usize),
+    Rows(usize),
 }
 
-fn parse_block(block: &str) -> Block {
-    block.lines().map(|l| l.chars().collect::<Vec<_>>()).collect()
+impl Axis {
+    fn score(&self) -> usize {
+        match self {
+            Axis::Columns(columns) => *columns,
+            Axis::Rows(rows) => 100 * rows,
+        }
+    }
 }
 
-fn mirrors_for_block(block: &Block, advanced: bool) -> (i64, i64) {
-    let (horizontal_mirrors, vertical_mirrors) = all_mirrors_for_block(block);
-
-    if horizontal_mirrors.len() > 1 {
-        panic!("More than one horizontal mirror: {:?}", horizontal_mirrors);
-    }
-    if vertical_mirrors.len() > 1 {
-        panic!("More than one vertical mirror: {:?}", vertical_mirrors);
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Axis::Columns(columns) => write!(f, "vertical line after column {}", columns),
+            Axis::Rows(rows) => write!(f, "horizontal line after row {}", rows),
+        }
     }
+}
 
-    let hm = *(horizontal_mirrors.iter().next().unwrap_or(&0)) as i64;
-    let vm = *(vertical_mirrors.iter().next().unwrap_or(&0)) as i64;
-
-    return if advanced {
-        let smudged_blocks = explode_block(block);
-        let smudged_mirrors = smudged_blocks
-            .iter()
-            .map(|b| all_mirrors_for_block(b))
-            .filter(|(shm_set, svm_set)| shm_set.len() > 0 || svm_set.len() > 0) // only consider options with reflections
-            .filter_map(|(shm_set, svm_set)| changed_mirror(hm, vm, shm_set, svm_set)) // only consider changed options
-            .collect::<HashSet<_>>();
-        if smudged_mirrors.len() > 0 {
-            if smudged_mirrors.len() > 1 {
-                panic!("More than one possible mirror: {:?}", smudged_mirrors);
+/// An axis the block is symmetric around once the cells in `smudges` are fixed. Every smudge is a pair of
+/// mirrored `(row, column)` cells that differ, either of which could be flipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reflection {
+    axis: Axis,
+    smudges: Vec<(Cell, Cell)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirrorError {
+    Parse(ParseError),
+    NoReflection { line: usize },
+    Ambiguous { line: usize, axes: Vec<Axis> },
+}
+
+impl fmt::Display for MirrorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MirrorError::Parse(e) => write!(f, "{}", e),
+            MirrorError::NoReflection { line } => write!(f, "block at line {} has no line of reflection", line),
+            MirrorError::Ambiguous { line, axes } => {
+                write!(f, "block at line {} has more than one line of reflection: {}", line, axes.iter().join(", "))
             }
-            let (shm, svm) = smudged_mirrors.iter().next().unwrap();
-            (*shm, *svm)
-        } else {
-            (0, 0)
         }
-    } else {
-        (hm, vm)
-    };
+    }
 }
 
-fn all_mirrors_for_block(block: &Block) -> (HashSet<usize>, HashSet<usize>) {
-    let transposed_block = &transpose(block);
+impl Error for MirrorError {}
 
-    let horizontal_mirror_candidates = find_mirror_candidates(block);
-    let vertical_mirror_candidates = find_mirror_candidates(transposed_block);
+impl From<ParseError> for MirrorError {
+    fn from(e: ParseError) -> MirrorError {
+        MirrorError::Parse(e)
+    }
+}
 
-    let horizontal_mirrors = candidate_intersections(&horizontal_mirror_candidates);
-    let vertical_mirrors = candidate_intersections(&vertical_mirror_candidates);
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, MirrorError> {
+    let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
+    let blocks = blocks(&data).into_iter()
+        .map(|(line, b)| Ok((line, parse_block(b, line)?)))
+        .collect::<Result<Vec<_>, ParseError>>()?;
+    let smudges = options.smudges.unwrap_or(if advanced { 1 } else { 0 });
+    let reflections = par_map(&blocks, |(line, block)| reflection_for_block(block, smudges, *line))
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if options.explain {
+        for ((line, _), reflection) in blocks.iter().zip(&reflections) {
+            let smudges = reflection.smudges.iter()
+                .map(|((r1, c1), (r2, c2))| format!("({}, {}) or ({}, {})", r1 + 1, c1 + 1, r2 + 1, c2 + 1))
+                .join(", ");
+            println!("Block at line {}: {}{}", line, reflection.axis, if smudges.is_empty() { smudges } else { format!(", smudge at {}", smudges) });
+        }
+    }
+    let result = reflections.iter().map(|r| r.axis.score()).sum::<usize>();
+    Ok(format!("{}", result))
+}
 
-    return (horizontal_mirrors, vertical_mirrors);
+fn parse_block(block: &str, first_line: usize) -> Result<Block, ParseError> {
+    char_grid(block, first_line, |c| matches!(c, '#' | '.').then_some(c))
 }
 
-fn explode_block(block: &Block) -> Vec<Block> {
-    let rows = block.len();
-    let cols = block[0].len();
-    let mut result = Vec::new();
-    for r in 0..rows {
-        for c in 0..cols {
-            let mut new_block = block.clone();
-            new_block[r][c] = if block[r][c] == '.' { '#' } else { '.' };
-            result.push(new_block);
-        }
+/// The single axis with exactly `smudges` mismatched pairs of cells.
+fn reflection_for_block(block: &Block, smudges: usize, line: usize) -> Result<Reflection, MirrorError> {
+    let mut reflections = find_reflections(block, smudges);
+    match reflections.len() {
+        0 => Err(MirrorError::NoReflection { line }),
+        1 => Ok(reflections.remove(0)),
+        _ => Err(MirrorError::Ambiguous { line, axes: reflections.iter().map(|r| r.axis).collect() }),
     }
-    return result;
 }
 
-fn find_mirror_candidates(block: &Block) -> Vec<HashSet<usize>> {
-    block.iter().map(find_row_mirrors).collect::<Vec<_>>()
+/// Every axis with exactly `smudges` mismatched pairs of cells.
+fn find_reflections(block: &Block, smudges: usize) -> Vec<Reflection> {
+    let columns = column_mismatches(block, smudges)
+        .into_iter()
+        .map(|(columns, mismatches)| Reflection { axis: Axis::Columns(columns), smudges: mismatches });
+    let rows = column_mismatches(&transpose(block), smudges)
+        .into_iter()
+        .map(|(rows, mismatches)| Reflection {
+            axis: Axis::Rows(rows),
+            smudges: mismatches.into_iter().map(|((c1, r1), (c2, r2))| ((r1, c1), (r2, c2))).collect(),
+        });
+    columns.chain(rows).filter(|r| r.smudges.len() == smudges).collect()
 }
 
-fn find_row_mirrors(row: &Vec<char>) -> HashSet<usize> {
-    let mut set = HashSet::new();
-    for i in 1..row.len() {
-        if row[i..].iter().zip(row[..i].iter().rev()).all(|(a, b)| *a == *b) {
-            set.insert(i);
+/// For every vertical axis, the mirrored cells that differ. Stops counting an axis once it has more than
+/// `limit` differences, as it can't be the one we're looking for.
+fn column_mismatches(block: &Block, limit: usize) -> Vec<(usize, Vec<(Cell, Cell)>)> {
+    let columns = block.first().map_or(0, |row| row.len());
+    (1..columns).map(|axis| {
+        let mut mismatches = Vec::new();
+        'rows: for (r, row) in block.iter().enumerate() {
+            for (left, right) in (0..axis).rev().zip(axis..columns) {
+                if row[left] != row[right] {
+                    mismatches.push(((r, left), (r, right)));
+                    if mismatches.len() > limit {
+                        break 'rows;
+                    }
+                }
+            }
         }
-    }
-    return set;
+        (axis, mismatches)
+    }).collect()
 }
 
 fn transpose(block: &Block) -> Block {
@@ -115,38 +164,6 @@ fn transpose(block: &Block) -> Block {
     }).collect()
 }
 
-fn candidate_intersections(candidates: &Vec<HashSet<usize>>) -> HashSet<usize> {
-    return if candidates.is_empty() {
-        HashSet::new()
-    } else {
-        candidates[1..].iter().fold(candidates[0].clone(), |acc, c| acc.intersection(c).map(|c| *c).collect())
-    };
-}
-
-fn changed_mirror(hm: i64, vm: i64, shm_set: HashSet<usize>, svm_set: HashSet<usize>) -> Option<(i64, i64)>{
-    let mut h = shm_set.clone();
-    let mut v = svm_set.clone();
-
-    h.remove(&(hm as usize));
-    v.remove(&(vm as usize));
-
-    if h.len() > 1 {
-        panic!("More than one horizontal mirror: {:?}", h);
-    }
-    if v.len() > 1 {
-        panic!("More than one vertical mirror: {:?}", v);
-    }
-
-    let shm = if h.len() > 0 { *h.iter().next().unwrap() as i64 } else { 0 };
-    let svm = if v.len() > 0 { *v.iter().next().unwrap() as i64 } else { 0 };
-
-    if shm != 0 && shm != hm {
-        Some((shm, 0))
-    } else if svm != 0 && svm != vm {
-        Some((0, svm))
-    } else {None}
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -161,14 +178,10 @@ mod tests {
 #.##..##.#.....
 #........#.##..";
 
-        let block = parse_block(test_block);
-        let transposed = transpose(&block);
-        let hmc = find_mirror_candidates(&block);
-        let vmc = find_mirror_candidates(&transposed);
-        let hm = candidate_intersections(&hmc);
-        let vm = candidate_intersections(&vmc);
-        assert!(hm.len() > 0);
-        assert_eq!(vm.len(), 0);
+        let block = parse_block(test_block, 1).unwrap();
+        let reflections = find_reflections(&block, 0);
+        assert!(reflections.iter().any(|r| matches!(r.axis, Axis::Columns(_))));
+        assert!(reflections.iter().all(|r| !matches!(r.axis, Axis::Rows(_))));
     }
 
     #[test]
@@ -184,9 +197,28 @@ mod tests {
 ...#####...##..
 ";
 
-        let block = parse_block(test_block);
-        let (hm, vm) = mirrors_for_block(&block, true);
-        assert_eq!(hm, 12);
-        assert_eq!(vm, 0);
+        let block = parse_block(test_block, 1).unwrap();
+        let reflection = reflection_for_block(&block, 1, 1).unwrap();
+        assert_eq!(reflection.axis, Axis::Columns(12));
+        assert_eq!(reflection.smudges.len(), 1);
+    }
+
+    #[test]
+    fn test_smudges_and_errors() {
+        let (_, second) = blocks(EXAMPLE)[1];
+        let reflection = reflection_for_block(&parse_block(second, 9).unwrap(), 1, 9).unwrap();
+        // the smudge is in the fifth column of the first two rows
+        assert_eq!(reflection, Reflection { axis: Axis::Rows(1), smudges: vec![((0, 4), (1, 4))] });
+
+        let symmetric = parse_block("#..#\n.##.\n.##.\n#..#", 3).unwrap();
+        assert_eq!(
+            reflection_for_block(&symmetric, 0, 3),
+            Err(MirrorError::Ambiguous { line: 3, axes: vec![Axis::Columns(2), Axis::Rows(2)] }),
+        );
+        assert_eq!(parse_block("#..\n.#", 5), Err(ParseError::new(6, "expected 3 cells in every row")));
+        let plain = parse_block("#.\n..", 1).unwrap();
+        assert_eq!(reflection_for_block(&plain, 0, 1), Err(MirrorError::NoReflection { line: 1 }));
+        // a single smudge away from being mirrored, along either axis
+        assert_eq!(find_reflections(&plain, 1).len(), 2);
     }
 }
\ No newline at end of file
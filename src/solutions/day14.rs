@@ -1,4 +1,14 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use nom::character::complete::{anychar, char};
+use nom::combinator::{map, map_opt, opt};
+use nom::multi::{many1, separated_list1};
+use nom::sequence::{pair, preceded};
+use crate::common::parsing::{char_grid, parse_line, unsigned, ParseError};
+use crate::common::render::{Color, Glyph, Render};
+use crate::solutions::{OptionError, Options};
 
 const EXAMPLE: &str = "O....#....
 O.OO#....#
@@ -11,14 +21,14 @@ O.#..O.#.#
 #....###..
 #OO..#....";
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum Space {
     Empty,
     Round,
     Square,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum Direction {
     North,
     East,
@@ -28,111 +38,248 @@ enum Direction {
 
 type Grid = Vec<Vec<Space>>;
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
-    let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
-    let mut grid = parse_grid(data);
-
-    return if advanced {
-        let goal_cycles = 1000000000;
-        let mut points = Vec::from([grid_points(&grid)]);
-        let mut grid_cache = HashMap::<String, u64>::new();
-        grid_cache.insert(grid_key(&grid), 0);
-
-        for i in 1..=goal_cycles {
-            cycle_grid(&mut grid);
-            points.push(grid_points(&grid));
-            let key = grid_key(&grid);
-            let last_iteration_seen = grid_cache.entry(key).or_insert(i);
-            if *last_iteration_seen < i {
-                // we've seen this grid before
-                let cycle_length = i - *last_iteration_seen;
-                let cycle_offset = *last_iteration_seen;
-
-                let finishing_cycle_offset = (goal_cycles - cycle_offset) % cycle_length;
-                let finishing_points = points[(cycle_offset + finishing_cycle_offset) as usize];
-                return format!("{}", finishing_points);
-            }
+impl Render for Grid {
+    fn glyphs(&self) -> Vec<Vec<Glyph>> {
+        self.iter().map(|row| row.iter().map(|s| match s {
+            Space::Empty => Glyph::new('.', Color::Gray),
+            Space::Round => Glyph::new('O', Color::Yellow),
+            Space::Square => Glyph::new('#', Color::Cyan),
+        }).collect()).collect()
+    }
+}
+
+/// One part of a tilt program: tilting in each of `directions` in turn, `repeats` times over.
+#[derive(Debug, PartialEq, Eq)]
+struct TiltStep {
+    directions: Vec<Direction>,
+    repeats: u64,
+}
+
+const SPIN_CYCLES: &str = "NWSE*1000000000";
+
+#[derive(Debug)]
+pub enum PlatformError {
+    Parse(ParseError),
+    Option(OptionError),
+}
+
+impl Display for PlatformError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PlatformError::Parse(e) => write!(f, "{}", e),
+            PlatformError::Option(e) => write!(f, "{}", e),
         }
+    }
+}
+
+impl Error for PlatformError {}
 
-        format!("{}", points.last().unwrap())
-    } else {
-        tilt_grid_max(&mut grid, Direction::North);
-        let points = grid_points(&grid);
-        format!("{}", points)
+impl From<ParseError> for PlatformError {
+    fn from(e: ParseError) -> PlatformError {
+        PlatformError::Parse(e)
+    }
+}
+
+impl From<OptionError> for PlatformError {
+    fn from(e: OptionError) -> PlatformError {
+        PlatformError::Option(e)
+    }
+}
+
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, PlatformError> {
+    let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
+    let mut grid = parse_grid(&data)?;
+    let default_program = if advanced { SPIN_CYCLES } else { "N" };
+    let program = parse_program(options.tilt.as_deref().unwrap_or(default_program))?;
+    let edge = match options.load_edge {
+        Some(edge) => parse_direction(edge).ok_or(OptionError::new("load-edge", edge, "use N, E, S or W"))?,
+        None => Direction::North,
     };
+
+    for step in &program {
+        run_step(&mut grid, step, options);
+    }
+    if options.tilt.is_some() {
+        for row in grid.glyphs() {
+            println!("{}", row.iter().map(|g| g.symbol).collect::<String>());
+        }
+    }
+    if let Some(visualizer) = &options.visualizer {
+        visualizer.show(&grid);
+    }
+    if let Some(exporter) = &options.exporter {
+        exporter.export(&grid);
+    }
+    Ok(format!("{}", load(&grid, edge)))
 }
 
-fn parse_grid(data: String) -> Grid {
-    data.lines().map(|l| l.chars().map(|c| match c {
-        '.' => Space::Empty,
-        'O' => Space::Round,
-        '#' => Space::Square,
-        _ => panic!("Invalid character: {}", c),
-    }).collect()).collect()
+/// Runs the tilts of `step` over and over. Once the platform repeats a state, the remaining repeats are cut
+/// down to what is left of the last, incomplete loop.
+fn run_step(grid: &mut Grid, step: &TiltStep, options: &Options) {
+    let mut seen = HashMap::<Vec<u64>, u64>::new();
+    seen.insert(round_rocks(grid), 0);
+    let mut done = 0;
+    let mut remaining = step.repeats;
+    while remaining > 0 {
+        tilt_sequence(grid, &step.directions);
+        if let Some(visualizer) = &options.visualizer {
+            visualizer.frame(grid);
+        }
+        if let Some(exporter) = &options.exporter {
+            exporter.frame(grid);
+        }
+        done += 1;
+        remaining -= 1;
+        if let Some(first_seen) = seen.insert(round_rocks(grid), done) {
+            // we've seen this grid before
+            remaining %= done - first_seen;
+            seen.clear();
+        }
+    }
 }
 
-fn cycle_grid(grid: &mut Grid) {
-    tilt_grid_max(grid, Direction::North);
-    tilt_grid_max(grid, Direction::East);
-    tilt_grid_max(grid, Direction::South);
-    tilt_grid_max(grid, Direction::West);
+fn parse_grid(data: &str) -> Result<Grid, ParseError> {
+    char_grid(data, 1, |c| match c {
+        '.' => Some(Space::Empty),
+        'O' => Some(Space::Round),
+        '#' => Some(Space::Square),
+        _ => None,
+    })
 }
 
-fn tilt_grid_max(grid: &mut Grid, direction: Direction) {
-    let mut rocks_rolled = 1; // set to anything but 0
-    while rocks_rolled > 0 {
-        rocks_rolled = tilt_grid_once(grid, direction);
+fn parse_direction(c: char) -> Option<Direction> {
+    match c.to_ascii_uppercase() {
+        'N' => Some(Direction::North),
+        'E' => Some(Direction::East),
+        'S' => Some(Direction::South),
+        'W' => Some(Direction::West),
+        _ => None,
     }
 }
 
-fn tilt_grid_once(grid: &mut Grid, direction: Direction) -> u32 {
-    let mut rocks_rolled = 0;
+/// Comma separated runs of tilts, each optionally repeated, e.g. `NWSE*1000000000` or `N,E,N`.
+fn parse_program(program: &str) -> Result<Vec<TiltStep>, OptionError> {
+    let direction = map_opt(anychar, parse_direction);
+    let step = map(
+        pair(many1(direction), opt(preceded(char('*'), unsigned))),
+        |(directions, repeats)| TiltStep { directions, repeats: repeats.unwrap_or(1) },
+    );
+    parse_line(1, program.trim(), separated_list1(char(','), step))
+        .map_err(|e| OptionError::new("tilt", program, e.message))
+}
+
+fn tilt_sequence(grid: &mut Grid, directions: &[Direction]) {
+    for direction in directions {
+        tilt_grid_max(grid, *direction);
+    }
+}
+
+/// Rolls every round rock as far as it goes in `direction`, in a single pass. Each row or column is walked
+/// from the edge rocks roll towards, remembering the first free space: round rocks move there, square rocks
+/// push it past themselves.
+fn tilt_grid_max(grid: &mut Grid, direction: Direction) {
     let rows = grid.len();
     let cols = grid[0].len();
-
-    let row_range = match direction {
-        Direction::North => 1..rows,
-        Direction::South => 0..(rows - 1),
-        _ => 0..rows,
-    };
-    let col_range = match direction {
-        Direction::East => 1..cols,
-        Direction::West => 0..(cols - 1),
-        _ => 0..cols,
+    let (lines, length) = match direction {
+        Direction::North | Direction::South => (cols, rows),
+        Direction::East | Direction::West => (rows, cols),
     };
-    let dst_tile = |y, x| match direction {
-        Direction::North => (y - 1, x),
-        Direction::East => (y, x - 1),
-        Direction::South => (y + 1, x),
-        Direction::West => (y, x + 1),
+    // position of the k-th space of a line, counting from the edge the rocks roll towards
+    let cell = |line: usize, k: usize| match direction {
+        Direction::North => (k, line),
+        Direction::South => (rows - 1 - k, line),
+        Direction::West => (line, k),
+        Direction::East => (line, cols - 1 - k),
     };
-    for y in row_range.clone() {
-        for x in col_range.clone() {
-            let (dst_y, dst_x) = dst_tile(y, x);
-            if grid[y][x] == Space::Round && grid[dst_y][dst_x] == Space::Empty {
-                grid[y][x] = Space::Empty;
-                grid[dst_y][dst_x] = Space::Round;
-                rocks_rolled += 1;
+
+    for line in 0..lines {
+        let mut free = 0;
+        for k in 0..length {
+            let (y, x) = cell(line, k);
+            match grid[y][x] {
+                Space::Square => free = k + 1,
+                Space::Round => {
+                    grid[y][x] = Space::Empty;
+                    let (free_y, free_x) = cell(line, free);
+                    grid[free_y][free_x] = Space::Round;
+                    free += 1;
+                }
+                Space::Empty => {}
             }
         }
     }
-    rocks_rolled
 }
 
-fn grid_key(grid: &Grid) -> String {
-    grid.iter().map(|row| row.iter().map(|s| match s {
-        Space::Empty => '.',
-        Space::Round => 'O',
-        Space::Square => '#',
-    }).collect::<String>()).collect::<Vec<_>>().join("")
+/// Positions of the round rocks, one bit per space. Square rocks never move, so this identifies the
+/// platform state for the cycle detection.
+fn round_rocks(grid: &Grid) -> Vec<u64> {
+    let mut bits = vec![0u64; (grid.len() * grid[0].len()).div_ceil(64)];
+    for (index, space) in grid.iter().flatten().enumerate() {
+        if *space == Space::Round {
+            bits[index / 64] |= 1 << (index % 64);
+        }
+    }
+    bits
 }
 
-
-fn grid_points(grid: &Grid) -> usize {
+/// Total load on the support beams along `edge`: every round rock weighs as many rows or columns as it is
+/// away from the opposite edge.
+fn load(grid: &Grid, edge: Direction) -> usize {
     let rows = grid.len();
+    let cols = grid[0].len();
     grid.iter()
         .enumerate()
-        .map(|(y, row)| row.iter().map(move |s| if *s == Space::Round { rows - y } else { 0 }))
-        .flatten()
+        .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, s)| (y, x, *s)))
+        .filter(|(_, _, s)| *s == Space::Round)
+        .map(|(y, x, _)| match edge {
+            Direction::North => rows - y,
+            Direction::South => y + 1,
+            Direction::West => cols - x,
+            Direction::East => x + 1,
+        })
         .sum()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_grid() {
+        let mut grid = parse_grid(EXAMPLE).unwrap();
+        run_step(&mut grid, &parse_program("NWSE").unwrap()[0], &Options::default());
+        let expected = parse_grid(".....#....
+....#...O#
+...OO##...
+.OO#......
+.....OOO#.
+.O#...O#.#
+....O#....
+......OOOO
+#...O###..
+#..OO#....").unwrap();
+        assert!(grid == expected);
+        assert_eq!(round_rocks(&grid), round_rocks(&expected));
+    }
+
+    #[test]
+    fn test_programs() {
+        let program = parse_program("NWSE*1000000000").unwrap();
+        let spin = vec![Direction::North, Direction::West, Direction::South, Direction::East];
+        assert_eq!(program, vec![TiltStep { directions: spin, repeats: 1000000000 }]);
+        assert_eq!(parse_program("N,E,N").unwrap().len(), 3);
+        assert!(parse_program("N,X").is_err());
+
+        let mut grid = parse_grid(EXAMPLE).unwrap();
+        for step in parse_program(SPIN_CYCLES).unwrap() {
+            run_step(&mut grid, &step, &Options::default());
+        }
+        assert_eq!(load(&grid, Direction::North), 64);
+
+        // tilting west leaves every rock of a row touching the west edge or a square rock
+        let mut grid = parse_grid("..O#.O\nO...O.").unwrap();
+        run_step(&mut grid, &parse_program("W").unwrap()[0], &Options::default());
+        assert_eq!(load(&grid, Direction::West), 6 + 2 + 6 + 5);
+        assert_eq!(load(&grid, Direction::East), 1 + 5 + 1 + 2);
+    }
+}
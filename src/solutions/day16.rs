@@ -1,5 +1,11 @@
-use std::cmp;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use itertools::Itertools;
+use crate::common::graph::strongly_connected_components;
+use crate::common::parallel::par_map;
+use crate::common::render::{frame_step, Color, Glyph, Render};
+use crate::solutions::Options;
 
 const EXAMPLE: &str = r".|...\....
 |.-.\.....
@@ -13,7 +19,7 @@ const EXAMPLE: &str = r".|...\....
 ..//.|....";
 
 #[derive(Debug, Eq, PartialEq, Hash)]
-enum Cell {
+pub enum Cell {
     Empty,
     MirrorForward,
     MirrorBackward,
@@ -32,13 +38,42 @@ impl Cell {
             _ => panic!("Invalid character: {}", c),
         }
     }
+
+    /// Directions a beam leaves the cell in when entering it going `direction`.
+    fn exits(&self, direction: Direction) -> (Direction, Option<Direction>) {
+        match (self, direction) {
+            (Cell::Empty, _) => (direction, None),
+            (Cell::MirrorForward, Direction::Up) => (Direction::Right, None),
+            (Cell::MirrorForward, Direction::Right) => (Direction::Up, None),
+            (Cell::MirrorForward, Direction::Down) => (Direction::Left, None),
+            (Cell::MirrorForward, Direction::Left) => (Direction::Down, None),
+            (Cell::MirrorBackward, Direction::Up) => (Direction::Left, None),
+            (Cell::MirrorBackward, Direction::Right) => (Direction::Down, None),
+            (Cell::MirrorBackward, Direction::Down) => (Direction::Right, None),
+            (Cell::MirrorBackward, Direction::Left) => (Direction::Up, None),
+            (Cell::SplitterHorizontal, Direction::Up | Direction::Down) => (Direction::Left, Some(Direction::Right)),
+            (Cell::SplitterVertical, Direction::Left | Direction::Right) => (Direction::Up, Some(Direction::Down)),
+            (Cell::SplitterHorizontal | Cell::SplitterVertical, _) => (direction, None),
+        }
+    }
+
+    fn symbol(&self) -> char {
+        match self {
+            Cell::Empty => '.',
+            Cell::MirrorForward => '/',
+            Cell::MirrorBackward => '\\',
+            Cell::SplitterHorizontal => '-',
+            Cell::SplitterVertical => '|',
+        }
+    }
 }
 
-type Grid = Vec<Vec<Cell>>;
-type Coords = (isize, isize);
+pub type Grid = Vec<Vec<Cell>>;
+/// Row and column of a cell.
+pub type Coords = (isize, isize);
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
-enum Direction {
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Direction {
     Up,
     Down,
     Left,
@@ -54,92 +89,433 @@ impl Direction {
             Direction::Right => (0, 1),
         }
     }
+
+    fn index(&self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        }
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Up => write!(f, "up"),
+            Direction::Down => write!(f, "down"),
+            Direction::Left => write!(f, "left"),
+            Direction::Right => write!(f, "right"),
+        }
+    }
+}
+
+/// Where a beam comes in: row, column and the direction it's heading.
+pub type Entry = (usize, usize, Direction);
+
+/// Everything a single beam, and the beams it splits into, did on its way through the contraption.
+#[derive(Debug)]
+pub struct BeamReport {
+    pub entry: Entry,
+    /// How many tiles the beams passed through.
+    pub energized: usize,
+    /// Splitters that split a beam in two.
+    pub splitters: Vec<Coords>,
+    /// Mirrors that turned a beam.
+    pub mirrors: Vec<Coords>,
+    /// Places where a beam came back to a cell it had passed before going the same way, through its own
+    /// mirrors and splitters. These are the loops it would otherwise run around forever, and where it's cut
+    /// off. Beams running into the path of another beam are cut off too, but aren't loops.
+    pub loops: Vec<(Coords, Direction)>,
+    /// How often beams entered every cell going up, down, left and right, loop arrivals included.
+    pub traversals: HashMap<Coords, [usize; 4]>,
+}
+
+/// Beam states in the order they are reached, and the states beams came back to.
+struct BeamPath {
+    states: Vec<(Coords, Direction)>,
+    /// The state every state was reached from, as an index into `states`. `None` for the entry.
+    sources: Vec<Option<usize>>,
+    /// Each state a beam came back to, and the state it came from, as indices into `states`.
+    revisits: Vec<(usize, usize)>,
+}
+
+impl BeamPath {
+    /// Whether the beam reaching `state` passed through `earlier` on its way there.
+    fn passes_through(&self, state: usize, earlier: usize) -> bool {
+        let mut current = Some(state);
+        while let Some(index) = current {
+            if index == earlier {
+                return true;
+            }
+            current = self.sources[index];
+        }
+        false
+    }
+}
+
+/// The contraption with every energized tile highlighted.
+struct BeamView<'a> {
+    grid: &'a Grid,
+    energized: HashSet<Coords>,
 }
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+impl Render for BeamView<'_> {
+    fn glyphs(&self) -> Vec<Vec<Glyph>> {
+        self.grid.iter().enumerate().map(|(y, row)| {
+            row.iter().enumerate().map(|(x, cell)| {
+                let energized = self.energized.contains(&(y as isize, x as isize));
+                match (cell, energized) {
+                    (Cell::Empty, true) => Glyph::new('#', Color::Yellow),
+                    (_, true) => Glyph::new(cell.symbol(), Color::Red),
+                    (_, false) => Glyph::new(cell.symbol(), Color::Gray),
+                }
+            }).collect()
+        }).collect()
+    }
+}
+
+/// How many times beams pass through every cell, summed over all tried entry points.
+struct HeatView<'a> {
+    grid: &'a Grid,
+    passes: HashMap<Coords, usize>,
+}
+
+impl Render for HeatView<'_> {
+    fn glyphs(&self) -> Vec<Vec<Glyph>> {
+        let max = self.passes.values().copied().max().unwrap_or(0);
+        self.grid.iter().enumerate().map(|(y, row)| {
+            row.iter().enumerate().map(|(x, cell)| {
+                let passes = self.passes.get(&(y as isize, x as isize)).copied().unwrap_or(0);
+                Glyph::new(cell.symbol(), Color::heat(passes, max))
+            }).collect()
+        }).collect()
+    }
+}
+
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> String {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
-    let grid: Grid = data.lines().map(|l| l.chars().map(Cell::from_char).collect()).collect();
-    let result = if advanced {
+    let grid = parse_grid(&data);
+    let entry_points: Vec<Entry> = if advanced {
         let rows = grid.len();
         let cols = grid[0].len();
-        let h_e = (0..rows).map(|r| {
-            cmp::max(
-                energized_count(&grid, r, 0, Direction::Right),
-                energized_count(&grid, r, cols - 1, Direction::Left),
-            )
-        }).max().unwrap();
-        let v_e = (0..cols).map(|c| {
-            cmp::max(
-                energized_count(&grid, 0, c, Direction::Down),
-                energized_count(&grid, rows - 1, c, Direction::Up),
-            )
-        }).max().unwrap();
-        cmp::max(h_e, v_e)
+        (0..rows)
+            .flat_map(|r| [(r, 0, Direction::Right), (r, cols - 1, Direction::Left)])
+            .chain((0..cols).flat_map(|c| [(0, c, Direction::Down), (rows - 1, c, Direction::Up)]))
+            .collect::<Vec<_>>()
     } else {
-        energized_count(&grid, 0, 0, Direction::Right)
+        vec![(0, 0, Direction::Right)]
     };
+    let beam_graph = BeamGraph::new(&grid);
+    let counts = par_map(&entry_points, |&(y, x, direction)| beam_graph.energized_count(y, x, direction));
+    let (&(y, x, direction), result) = entry_points.iter().zip(counts).max_by_key(|(_, count)| *count).unwrap();
+
+    if let Some(visualizer) = &options.visualizer {
+        let beam = energized_cells(&grid, y, x, direction);
+        for end in (1..beam.len()).step_by(frame_step(beam.len())) {
+            visualizer.frame(&BeamView { grid: &grid, energized: beam[..end].iter().copied().collect() });
+        }
+        visualizer.show(&BeamView { grid: &grid, energized: beam.into_iter().collect() });
+    }
+    if let Some(exporter) = &options.exporter {
+        let mut passes = HashMap::new();
+        for beam in par_map(&entry_points, |&(y, x, direction)| energized_cells(&grid, y, x, direction)) {
+            for cell in beam {
+                *passes.entry(cell).or_insert(0) += 1;
+            }
+        }
+        exporter.export(&HeatView { grid: &grid, passes });
+    }
+    if options.explain {
+        explain(&grid, &analyze_beam(&grid, (y, x, direction)));
+    }
     format!("{}", result)
 }
 
-fn energized_count(grid: &Grid, start_y: usize, start_x: usize, start_direction: Direction) -> usize {
-    follow_beam(&grid, start_y as isize, start_x as isize, start_direction, &mut HashSet::new())
-        .into_iter()
-        .collect::<HashSet<Coords>>()
-        .len()
-}
-
-fn follow_beam(grid: &Grid, start_y: isize, start_x: isize, start_direction: Direction, visited_cells: &mut HashSet<(Coords, Direction)>) -> Vec<Coords> {
-    let rows = grid.len() as isize;
-    let cols = grid[0].len() as isize;
-    let mut pos = (start_y, start_x);
-    let mut direction = start_direction;
-    let mut result = Vec::new();
-    while pos.0 >= 0 && pos.0 < rows && pos.1 >= 0 && pos.1 < cols {
-        if visited_cells.contains(&(pos, direction)) {
-            break;
-        }
-        result.push(pos);
-        visited_cells.insert((pos, direction));
-        match &grid[pos.0 as usize][pos.1 as usize] {
-            Cell::Empty => {}
-            Cell::MirrorForward => {
-                direction = match direction {
-                    Direction::Up => Direction::Right,
-                    Direction::Right => Direction::Up,
-                    Direction::Down => Direction::Left,
-                    Direction::Left => Direction::Down,
-                };
-            }
-            Cell::MirrorBackward => {
-                direction = match direction {
-                    Direction::Up => Direction::Left,
-                    Direction::Right => Direction::Down,
-                    Direction::Down => Direction::Right,
-                    Direction::Left => Direction::Up,
+fn explain(grid: &Grid, report: &BeamReport) {
+    let (y, x, direction) = report.entry;
+    println!("Best entry: row {}, column {}, going {}, energizing {} tiles", y + 1, x + 1, direction, report.energized);
+    println!("Splitters used: {}, mirrors used: {}", report.splitters.len(), report.mirrors.len());
+    for ((y, x), direction) in &report.loops {
+        println!("Loop back to row {}, column {}, going {}", y + 1, x + 1, direction);
+    }
+    for direction in [Direction::Up, Direction::Down, Direction::Left, Direction::Right] {
+        println!("Times beams entered every tile going {}:", direction);
+        for (y, row) in grid.iter().enumerate() {
+            let counts = (0..row.len()).map(|x| {
+                match report.traversals.get(&(y as isize, x as isize)).map_or(0, |c| c[direction.index()]) {
+                    0 => '.',
+                    count => char::from_digit(count as u32, 10).unwrap_or('+'),
                 }
+            });
+            println!("{}", counts.collect::<String>());
+        }
+    }
+}
+
+/// The contraption, one cell per character. Panics on anything but `.`, `/`, `\`, `-` and `|`.
+pub fn parse_grid(data: &str) -> Grid {
+    data.lines().map(|l| l.chars().map(Cell::from_char).collect()).collect()
+}
+
+/// Follows the beam coming in at `entry` and reports what it ran into.
+pub fn analyze_beam(grid: &Grid, entry: Entry) -> BeamReport {
+    let (y, x, direction) = entry;
+    let path = follow_beam(grid, y as isize, x as isize, direction);
+    let mut traversals = HashMap::<Coords, [usize; 4]>::new();
+    let mut splitters = HashSet::new();
+    let mut mirrors = HashSet::new();
+    for &(pos, direction) in path.states.iter().chain(path.revisits.iter().map(|(state, _)| &path.states[*state])) {
+        traversals.entry(pos).or_default()[direction.index()] += 1;
+    }
+    for &(pos, direction) in &path.states {
+        let cell = cell_at(grid, pos).unwrap();
+        match cell.exits(direction) {
+            (_, Some(_)) => { splitters.insert(pos); }
+            (exit, None) if exit != direction => { mirrors.insert(pos); }
+            _ => {}
+        }
+    }
+    BeamReport {
+        entry,
+        energized: traversals.len(),
+        splitters: splitters.into_iter().sorted().collect(),
+        mirrors: mirrors.into_iter().sorted().collect(),
+        loops: path.revisits.iter()
+            .filter(|(state, source)| path.passes_through(*source, *state))
+            .map(|(state, _)| path.states[*state])
+            .collect(),
+        traversals,
+    }
+}
+
+/// Every cell the beam passes through, in the order it gets there. Cells can repeat.
+fn energized_cells(grid: &Grid, start_y: usize, start_x: usize, start_direction: Direction) -> Vec<Coords> {
+    follow_beam(grid, start_y as isize, start_x as isize, start_direction).states.into_iter().map(|(pos, _)| pos).collect()
+}
+
+/// Follows the beam and everything it splits into, depth first. Split-off beams wait on an explicit stack
+/// rather than in recursive calls. A beam stops when it leaves the grid, or when it enters a cell going the
+/// same way as a beam did before, as it would only follow the same path again.
+fn follow_beam(grid: &Grid, start_y: isize, start_x: isize, start_direction: Direction) -> BeamPath {
+    let mut visited_cells = HashMap::new();
+    let mut path = BeamPath { states: Vec::new(), sources: Vec::new(), revisits: Vec::new() };
+    let mut beams = vec![((start_y, start_x), start_direction, None)];
+    while let Some((mut pos, mut direction, mut source)) = beams.pop() {
+        while let Some(cell) = cell_at(grid, pos) {
+            if let Some(&state) = visited_cells.get(&(pos, direction)) {
+                // only the entry has no source, and it can't be come back to before it's been visited
+                path.revisits.push((state, source.unwrap()));
+                break;
             }
-            Cell::SplitterHorizontal => {
-                if direction == Direction::Up || direction == Direction::Down {
-                    let l_beam = follow_beam(grid, pos.0, pos.1 - 1, Direction::Left, visited_cells);
-                    let r_beam = follow_beam(grid, pos.0, pos.1 + 1, Direction::Right, visited_cells);
-                    result.extend(l_beam);
-                    result.extend(r_beam);
-                    return result;
-                }
+            let state = path.states.len();
+            visited_cells.insert((pos, direction), state);
+            path.states.push((pos, direction));
+            path.sources.push(source);
+            let (exit, split) = cell.exits(direction);
+            if let Some(split) = split {
+                beams.push((step(pos, split), split, Some(state)));
+            }
+            direction = exit;
+            pos = step(pos, direction);
+            source = Some(state);
+        }
+    }
+    path
+}
+
+fn cell_at(grid: &Grid, pos: Coords) -> Option<&Cell> {
+    grid.get(usize::try_from(pos.0).ok()?)?.get(usize::try_from(pos.1).ok()?)
+}
+
+fn step(pos: Coords, direction: Direction) -> Coords {
+    let v = direction.next_vector();
+    (pos.0 + v.0, pos.1 + v.1)
+}
+
+/// Set of grid cells, one bit per cell.
+#[derive(Debug, Clone)]
+struct CellSet {
+    bits: Vec<u64>,
+}
+
+impl CellSet {
+    fn new(cells: usize) -> CellSet {
+        CellSet { bits: vec![0; cells.div_ceil(64)] }
+    }
+
+    fn insert(&mut self, cell: usize) {
+        self.bits[cell / 64] |= 1 << (cell % 64);
+    }
+
+    fn union_with(&mut self, other: &CellSet) {
+        for (a, b) in self.bits.iter_mut().zip(&other.bits) {
+            *a |= b;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+}
+
+/// Straight-line run of a beam, through mirrors and along splitters, up to the splitter that splits it.
+struct Segment {
+    cells: Vec<usize>,
+    /// Index of the splitter the segment ends in, if it doesn't leave the grid or run in circles.
+    splitter: Option<usize>,
+}
+
+/// The contraption as a graph of splitters. Every splitter a beam hits from the side sends out the same two
+/// segments, no matter where the beam came from, so each splitter gets the set of cells energized from it.
+/// Splitters feeding each other are condensed into strongly connected components first, so that every set
+/// is built once, from the sets of the components downstream.
+struct BeamGraph<'a> {
+    grid: &'a Grid,
+    splitters: HashMap<Coords, usize>,
+    /// Component of every splitter.
+    component: Vec<usize>,
+    /// Cells energized from any splitter of a component.
+    energized: Vec<CellSet>,
+}
+
+impl<'a> BeamGraph<'a> {
+    fn new(grid: &'a Grid) -> BeamGraph<'a> {
+        let splitter_cells = grid.iter().enumerate().flat_map(|(y, row)| {
+            row.iter().enumerate()
+                .filter(|(_, c)| matches!(c, Cell::SplitterHorizontal | Cell::SplitterVertical))
+                .map(move |(x, _)| (y as isize, x as isize))
+        }).collect::<Vec<_>>();
+        let splitters = splitter_cells.iter().enumerate().map(|(i, c)| (*c, i)).collect::<HashMap<_, _>>();
+        let mut graph = BeamGraph { grid, splitters, component: vec![], energized: vec![] };
+
+        // cells lit by the two beams leaving every splitter, and the splitters those beams end in
+        let cell_count = grid.len() * grid[0].len();
+        let mut own_cells = Vec::with_capacity(splitter_cells.len());
+        let mut successors = Vec::with_capacity(splitter_cells.len());
+        for &pos in &splitter_cells {
+            let mut cells = CellSet::new(cell_count);
+            cells.insert(graph.cell_index(pos));
+            let mut next = Vec::new();
+            let (first, second) = match grid[pos.0 as usize][pos.1 as usize] {
+                Cell::SplitterHorizontal => (Direction::Left, Direction::Right),
+                _ => (Direction::Up, Direction::Down),
+            };
+            for direction in [first, second] {
+                let segment = graph.segment(step(pos, direction), direction);
+                segment.cells.iter().for_each(|c| cells.insert(*c));
+                next.extend(segment.splitter);
             }
-            Cell::SplitterVertical => {
-                if direction == Direction::Left || direction == Direction::Right {
-                    let u_beam = follow_beam(grid, pos.0 - 1, pos.1, Direction::Up, visited_cells);
-                    let d_beam = follow_beam(grid, pos.0 + 1, pos.1, Direction::Down, visited_cells);
-                    result.extend(u_beam);
-                    result.extend(d_beam);
-                    return result;
+            own_cells.push(cells);
+            successors.push(next);
+        }
+
+        // components come downstream first, so their successors are always done already
+        let (component, count) = strongly_connected_components(&successors);
+        let mut members = vec![Vec::new(); count];
+        for (splitter, c) in component.iter().enumerate() {
+            members[*c].push(splitter);
+        }
+        let mut component_cells: Vec<CellSet> = Vec::with_capacity(count);
+        for splitters in &members {
+            let mut cells = CellSet::new(cell_count);
+            for &splitter in splitters {
+                cells.union_with(&own_cells[splitter]);
+                for &next in &successors[splitter] {
+                    if component[next] != component[splitter] {
+                        cells.union_with(&component_cells[component[next]]);
+                    }
                 }
             }
+            component_cells.push(cells);
         }
-        let v = direction.next_vector();
-        pos = (pos.0 + v.0, pos.1 + v.1);
+        graph.component = component;
+        graph.energized = component_cells;
+        graph
+    }
+
+    fn cell_index(&self, pos: Coords) -> usize {
+        pos.0 as usize * self.grid[0].len() + pos.1 as usize
     }
 
-    return result;
+    /// Follows a beam until it leaves the grid, gets split, or comes back to where it has already been.
+    fn segment(&self, start: Coords, start_direction: Direction) -> Segment {
+        let mut visited = HashSet::new();
+        let mut cells = Vec::new();
+        let (mut pos, mut direction) = (start, start_direction);
+        while let Some(cell) = cell_at(self.grid, pos) {
+            if !visited.insert((pos, direction)) {
+                break;
+            }
+            cells.push(self.cell_index(pos));
+            let (exit, split) = cell.exits(direction);
+            if split.is_some() {
+                return Segment { cells, splitter: Some(self.splitters[&pos]) };
+            }
+            direction = exit;
+            pos = step(pos, direction);
+        }
+        Segment { cells, splitter: None }
+    }
+
+    fn energized_count(&self, start_y: usize, start_x: usize, start_direction: Direction) -> usize {
+        let segment = self.segment((start_y as isize, start_x as isize), start_direction);
+        let mut cells = match segment.splitter {
+            Some(splitter) => self.energized[self.component[splitter]].clone(),
+            None => CellSet::new(self.grid.len() * self.grid[0].len()),
+        };
+        segment.cells.iter().for_each(|c| cells.insert(*c));
+        cells.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beam_graph_matches_following_the_beam() {
+        let grid = parse_grid(EXAMPLE);
+        let beam_graph = BeamGraph::new(&grid);
+        let rows = grid.len();
+        let cols = grid[0].len();
+        let entries = (0..rows)
+            .flat_map(|r| [(r, 0, Direction::Right), (r, cols - 1, Direction::Left)])
+            .chain((0..cols).flat_map(|c| [(0, c, Direction::Down), (rows - 1, c, Direction::Up)]));
+        for (y, x, direction) in entries {
+            let followed = energized_cells(&grid, y, x, direction).into_iter().collect::<HashSet<_>>().len();
+            assert_eq!(beam_graph.energized_count(y, x, direction), followed);
+        }
+        assert_eq!(beam_graph.energized_count(0, 0, Direction::Right), 46);
+    }
+
+    #[test]
+    fn test_analyze_beam() {
+        let grid = parse_grid(EXAMPLE);
+        let report = analyze_beam(&grid, (0, 0, Direction::Right));
+        assert_eq!(report.energized, 46);
+        assert_eq!(report.splitters.len(), 7);
+        assert_eq!(report.mirrors, vec![(0, 5), (6, 4), (6, 6), (6, 7), (7, 4)]);
+        // the beam leaving the splitter at row 7, column 1 to the right comes back to it, and is cut off when
+        // it leaves to the right again. Leaving to the left, it only runs into the beam that went there first.
+        assert_eq!(report.loops, vec![((7, 2), Direction::Right), ((1, 1), Direction::Down)]);
+        assert_eq!(report.traversals[&(7, 0)], [0, 0, 2, 0]);
+        assert_eq!(report.traversals[&(6, 6)], [0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_long_beams_do_not_recurse() {
+        // a staircase of splitters, each one splitting a beam coming from the one before
+        let size = 400;
+        let grid: Grid = (0..size).map(|y| (0..size).map(|x| match (x == y, x == y + 1) {
+            (true, _) => Cell::SplitterHorizontal,
+            (_, true) => Cell::SplitterVertical,
+            _ => Cell::Empty,
+        }).collect()).collect();
+        let beam_graph = BeamGraph::new(&grid);
+        let followed = energized_cells(&grid, 0, 0, Direction::Down).into_iter().collect::<HashSet<_>>().len();
+        assert_eq!(beam_graph.energized_count(0, 0, Direction::Down), followed);
+        assert!(followed > 2 * size);
+    }
 }
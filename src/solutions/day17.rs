@@ -1,4 +1,13 @@
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use nom::character::complete::char;
+use nom::sequence::separated_pair;
+use crate::common::parsing::{char_grid, parse_line, unsigned, ParseError};
+use crate::common::render::{frame_step, Color, Glyph, Render};
+use crate::solutions::{OptionError, Options};
 
 const EXAMPLE: &str = "2413432311323
 3215453535623
@@ -17,7 +26,7 @@ const EXAMPLE: &str = "2413432311323
 type Map = Vec<Vec<u8>>;
 type Coords = (usize, usize);
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, Ord, PartialOrd)]
 enum Direction {
     Up,
     Down,
@@ -25,148 +34,298 @@ enum Direction {
     Right,
 }
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
-    let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
-    let map = parse_map(&data);
-    let straight_range = if advanced { (4, 10) } else { (1, 3) };
-    let (result, _) = find_path(&map, straight_range);
-    format!("{}", result)
+impl Direction {
+    const ALL: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+    fn opposite(&self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
+    fn arrow(&self) -> char {
+        match self {
+            Direction::Up => '^',
+            Direction::Down => 'v',
+            Direction::Left => '<',
+            Direction::Right => '>',
+        }
+    }
 }
 
-fn parse_map(data: &str) -> Map {
-    data.lines().map(|l| l.chars().map(|c| c as u8 - '0' as u8).collect()).collect()
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Up => write!(f, "up"),
+            Direction::Down => write!(f, "down"),
+            Direction::Left => write!(f, "left"),
+            Direction::Right => write!(f, "right"),
+        }
+    }
 }
 
-fn find_path(map: &Map, straight_range: (u8, u8)) -> (u64, Vec<Coords>) {
-    let start_y = 0usize;
-    let start_x = 0usize;
-    let end_y = map.len() - 1;
-    let end_x = map[0].len() - 1;
+/// Where the crucible is, which way it moved last and how many blocks in a row it has moved that way. A run
+/// of 0 means it hasn't moved yet, so it can go anywhere.
+type State = (Coords, Direction, usize);
 
-    let mut distances = HashMap::<(Coords, Direction, u8), u64>::new();
-    distances.insert(((start_y, start_x), Direction::Right, 1), 0);
+/// How many blocks in a row a crucible has to, and may, move before turning.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Straight {
+    min: usize,
+    max: usize,
+}
 
-    let mut queue = VecDeque::<(u64, Vec<Coords>)>::new();
-    queue.push_back((0, vec![(start_y, start_x)]));
+/// Cheapest route found for the crucible: the heat it loses and every state along the way, start included.
+#[derive(Debug)]
+struct Route {
+    heat_loss: u64,
+    states: Vec<State>,
+}
 
-    let mut best_distance = u64::MAX;
-    let mut best_path = vec![];
+impl Route {
+    fn tiles(&self) -> Vec<Coords> {
+        self.states.iter().map(|(pos, _, _)| *pos).collect()
+    }
 
-    while let Some((cost, path)) = queue.pop_front() {
-        let key = path_key(&path);
-        let pos = path.last().unwrap();
-        if *pos == (end_y, end_x) {
-            distances.insert(key, cost);
-            if cost < best_distance {
-                best_distance = cost;
-                best_path = path;
+    /// Tiles where the crucible sets off in a new direction, with the direction and the number of blocks it
+    /// moves that way.
+    fn turns(&self) -> Vec<(Coords, Direction, usize)> {
+        let mut turns: Vec<(Coords, Direction, usize)> = Vec::new();
+        for window in self.states.windows(2) {
+            let ((from, _, _), (_, direction, run)) = (window[0], window[1]);
+            match turns.last_mut() {
+                Some(turn) if run > 1 => turn.2 = run,
+                _ => turns.push((from, direction, run)),
             }
-            continue;
         }
+        turns
+    }
+}
 
-        // If we have already found a shorter path, skip
-        if cost > *distances.get(&key).unwrap_or(&u64::MAX) {
-            continue;
-        }
+#[derive(Debug)]
+pub enum CrucibleError {
+    Parse(ParseError),
+    Option(OptionError),
+    /// The crucible has to move at least `min` blocks in a row but may move at most `max`.
+    InvalidStraight { min: usize, max: usize },
+    OutOfBounds(Coords),
+    NoRoute,
+}
 
-        let next_tiles = all_next_tiles(map, pos)
-            .into_iter()
-            .filter(|t| filter_last_tile(t, &path))
-            .filter(|t| filter_consecutive_straight(t, &path, straight_range))
-            .collect::<Vec<_>>();
-        for next_tile in next_tiles {
-            let next_cost = cost + map[next_tile.0][next_tile.1] as u64;
-            let mut next_path = path.clone();
-            next_path.push(next_tile);
-            let next_key = path_key(&next_path);
-
-            if next_cost >= *distances.get(&next_key).unwrap_or(&u64::MAX) || next_cost >= best_distance {
-                continue;
+impl Display for CrucibleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CrucibleError::Parse(e) => write!(f, "{}", e),
+            CrucibleError::Option(e) => write!(f, "{}", e),
+            CrucibleError::InvalidStraight { min, max } => {
+                write!(f, "the crucible can't move at least {} and at most {} blocks in a row", min, max)
             }
-            distances.insert(next_key, next_cost);
-            queue.push_back((next_cost, next_path));
+            CrucibleError::OutOfBounds((y, x)) => write!(f, "row {}, column {} is outside the map", y + 1, x + 1),
+            CrucibleError::NoRoute => write!(f, "the crucible can't reach the goal"),
         }
     }
+}
 
-    (best_distance, best_path)
+impl Error for CrucibleError {}
+
+impl From<ParseError> for CrucibleError {
+    fn from(e: ParseError) -> CrucibleError {
+        CrucibleError::Parse(e)
+    }
 }
 
-fn all_next_tiles(map: &Map, pos: &Coords) -> Vec<Coords> {
-    let rows = map.len() as i32;
-    let cols = map[0].len() as i32;
-    let vectors = [(1, 0), (-1, 0), (0, 1), (0, -1)];
-    vectors
-        .iter()
-        .map(|&(dy, dx)| (dy + pos.0 as i32, dx + pos.1 as i32))
-        .filter(|&(y, x)| y >= 0 && x >= 0 && y < rows && x < cols)
-        .map(|(y, x)| (y as usize, x as usize))
-        .collect()
+impl From<OptionError> for CrucibleError {
+    fn from(e: OptionError) -> CrucibleError {
+        CrucibleError::Option(e)
+    }
 }
 
-fn filter_last_tile(tile_candidate: &Coords, current_path: &Vec<Coords>) -> bool {
-    current_path.iter().rev().take(2).all(|&p| p != *tile_candidate)
+/// Heat loss digits, shaded from dark (low) to light (high), with the crucible route highlighted.
+struct PathView<'a> {
+    map: &'a Map,
+    path: &'a [Coords],
 }
 
-fn filter_consecutive_straight(tile_candidate: &Coords, current_path: &Vec<Coords>, limits: (u8, u8)) -> bool {
-    let (last_tile, direction, straights) = path_key(current_path);
-    match (direction, find_tile_vector(&tile_candidate, &last_tile)) {
-        (Direction::Up, (-1, 0)) => straights < limits.1,
-        (Direction::Up, _) => straights >= limits.0,
-        (Direction::Down, (1, 0)) => straights < limits.1,
-        (Direction::Down, _) => straights >= limits.0,
-        (Direction::Left, (0, -1)) => straights < limits.1,
-        (Direction::Left, _) => straights >= limits.0,
-        (Direction::Right, (0, 1)) => straights < limits.1,
-        (Direction::Right, _) => straights >= limits.0,
+impl Render for PathView<'_> {
+    fn glyphs(&self) -> Vec<Vec<Glyph>> {
+        let path_tiles = self.path.iter().collect::<HashSet<_>>();
+        self.map.iter().enumerate().map(|(y, row)| {
+            row.iter().enumerate().map(|(x, heat_loss)| {
+                let symbol = char::from_digit(*heat_loss as u32, 10).unwrap_or('?');
+                let color = if path_tiles.contains(&(y, x)) {
+                    Color::Yellow
+                } else {
+                    let shade = 40 + 20 * heat_loss.min(&9);
+                    Color::Rgb(shade, shade, shade)
+                };
+                Glyph::new(symbol, color)
+            }).collect()
+        }).collect()
     }
 }
 
-fn path_key(path: &Vec<Coords>) -> (Coords, Direction, u8) {
-    let last = if let Some(&last) = path.iter().rev().next() {
-        last
-    } else {
-        return ((0, 0), Direction::Right, 0);
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, CrucibleError> {
+    let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
+    let map = parse_map(&data)?;
+    let default = if advanced { Straight { min: 4, max: 10 } } else { Straight { min: 1, max: 3 } };
+    let straight = Straight {
+        min: options.min_straight.unwrap_or(default.min),
+        max: options.max_straight.unwrap_or(default.max),
     };
-    let next_last = if let Some(&next_last) = path.iter().rev().skip(1).next() {
-        next_last
-    } else {
-        return (last, Direction::Right, 0);
+    if straight.max == 0 || straight.min > straight.max {
+        return Err(CrucibleError::InvalidStraight { min: straight.min, max: straight.max });
+    }
+    let start = match &options.start {
+        Some(start) => parse_coords("start", start)?,
+        None => (0, 0),
     };
-    let expected_tile_diff = find_tile_vector(&last, &next_last);
-    let direction = match expected_tile_diff {
-        (1, 0) => Direction::Down,
-        (-1, 0) => Direction::Up,
-        (0, 1) => Direction::Right,
-        (0, -1) => Direction::Left,
-        _ => unreachable!("{:?} {:?}", last, next_last)
+    let goal = match &options.goal {
+        Some(goal) => parse_coords("goal", goal)?,
+        None => (map.len() - 1, map[0].len() - 1),
     };
-    let mut straights = 0;
-    for (&a, &b) in path.iter().rev().zip(path.iter().rev().skip(1)) {
-        let tile_diff = (a.0 as i32 - b.0 as i32, a.1 as i32 - b.1 as i32);
-        if tile_diff == expected_tile_diff {
-            straights += 1;
-        } else {
-            break;
+    if let Some(&outside) = [start, goal].iter().find(|(y, x)| *y >= map.len() || *x >= map[0].len()) {
+        return Err(CrucibleError::OutOfBounds(outside));
+    }
+    let route = find_route(&map, straight, start, goal).ok_or(CrucibleError::NoRoute)?;
+
+    if options.explain {
+        explain(&map, &route);
+    }
+    let path = route.tiles();
+    if let Some(visualizer) = &options.visualizer {
+        for end in (1..path.len()).step_by(frame_step(path.len())) {
+            visualizer.frame(&PathView { map: &map, path: &path[..end] });
+        }
+        visualizer.show(&PathView { map: &map, path: &path });
+    }
+    if let Some(exporter) = &options.exporter {
+        for end in (1..path.len()).step_by(frame_step(path.len())) {
+            exporter.frame(&PathView { map: &map, path: &path[..end] });
         }
+        exporter.export(&PathView { map: &map, path: &path });
     }
-    (last, direction, straights)
+    Ok(format!("{}", route.heat_loss))
 }
 
-fn find_tile_vector(a: &Coords, b: &Coords) -> (i32, i32) {
-    (a.0 as i32 - b.0 as i32, a.1 as i32 - b.1 as i32)
+/// Prints the turns the crucible takes, and the map with the route drawn in as in the puzzle description.
+fn explain(map: &Map, route: &Route) {
+    for ((y, x), direction, run) in route.turns() {
+        println!("Row {}, column {}: {} {}", y + 1, x + 1, direction, run);
+    }
+    let arrows = route.states.iter().skip(1).map(|(pos, direction, _)| (*pos, direction.arrow())).collect::<HashMap<_, _>>();
+    for (y, row) in map.iter().enumerate() {
+        let line = row.iter().enumerate().map(|(x, heat_loss)| match arrows.get(&(y, x)) {
+            Some(arrow) => *arrow,
+            None => char::from_digit(*heat_loss as u32, 10).unwrap_or('?'),
+        });
+        println!("{}", line.collect::<String>());
+    }
+}
+
+fn parse_map(data: &str) -> Result<Map, ParseError> {
+    char_grid(data, 1, |c| c.to_digit(10).map(|d| d as u8))
+}
+
+/// `ROW,COLUMN`, counting from 1.
+fn parse_coords(flag: &'static str, coords: &str) -> Result<Coords, OptionError> {
+    let (y, x): (usize, usize) = parse_line(1, coords.trim(), separated_pair(unsigned, char(','), unsigned))
+        .map_err(|e| OptionError::new(flag, coords, e.message))?;
+    if y == 0 || x == 0 {
+        return Err(OptionError::new(flag, coords, "rows and columns count from 1"));
+    }
+    Ok((y - 1, x - 1))
+}
+
+/// Dijkstra over the crucible states. The goal only counts once the crucible has moved far enough in a row
+/// to be able to stop.
+fn find_route(map: &Map, straight: Straight, start: Coords, goal: Coords) -> Option<Route> {
+    let start_state = (start, Direction::Right, 0);
+    let mut distances = HashMap::<State, u64>::new();
+    let mut previous = HashMap::<State, State>::new();
+    distances.insert(start_state, 0);
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse((0, start_state)));
+
+    while let Some(Reverse((cost, state))) = queue.pop() {
+        let (pos, direction, run) = state;
+        if pos == goal && run >= straight.min {
+            let mut states = vec![state];
+            while let Some(prev) = previous.get(states.last().unwrap()) {
+                states.push(*prev);
+            }
+            states.reverse();
+            return Some(Route { heat_loss: cost, states });
+        }
+        if cost > distances[&state] {
+            continue;
+        }
+
+        for next_direction in Direction::ALL {
+            let next_run = if run == 0 {
+                1
+            } else if next_direction == direction {
+                if run == straight.max { continue; }
+                run + 1
+            } else {
+                if next_direction == direction.opposite() || run < straight.min { continue; }
+                1
+            };
+            let Some(next_pos) = next_tile(map, pos, next_direction) else { continue };
+            let next_state = (next_pos, next_direction, next_run);
+            let next_cost = cost + map[next_pos.0][next_pos.1] as u64;
+            if next_cost < *distances.get(&next_state).unwrap_or(&u64::MAX) {
+                distances.insert(next_state, next_cost);
+                previous.insert(next_state, state);
+                queue.push(Reverse((next_cost, next_state)));
+            }
+        }
+    }
+    None
 }
 
+fn next_tile(map: &Map, pos: Coords, direction: Direction) -> Option<Coords> {
+    let (y, x) = pos;
+    let next = match direction {
+        Direction::Up => (y.checked_sub(1)?, x),
+        Direction::Down => (y + 1, x),
+        Direction::Left => (y, x.checked_sub(1)?),
+        Direction::Right => (y, x + 1),
+    };
+    (next.0 < map.len() && next.1 < map[0].len()).then_some(next)
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn corner_to_corner(map: &Map, straight: Straight) -> Option<Route> {
+        find_route(map, straight, (0, 0), (map.len() - 1, map[0].len() - 1))
+    }
+
     #[test]
-    fn test_key() {
-        assert_eq!(path_key(&vec![(0, 0), (0, 1), (0, 2)]), ((0, 2), Direction::Right, 2));
-        assert_eq!(path_key(&vec![(1, 0), (0, 0), (0, 1), (0, 2)]), ((0, 2), Direction::Right, 2));
-        assert_eq!(path_key(&vec![(0, 0), (0, 1), (0, 2), (0, 3)]), ((0, 3), Direction::Right, 3));
-        assert_eq!(path_key(&vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 4)]), ((0, 4), Direction::Right, 4));
-        assert_eq!(path_key(&vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5)]), ((0, 5), Direction::Right, 5));
-    }
-}
\ No newline at end of file
+    fn test_find_route() {
+        let map = parse_map(EXAMPLE).unwrap();
+        let route = corner_to_corner(&map, Straight { min: 1, max: 3 }).unwrap();
+        assert_eq!(route.heat_loss, 102);
+        assert_eq!(route.turns()[0], ((0, 0), Direction::Right, 2));
+        assert!(route.turns().iter().all(|(_, _, run)| (1..=3).contains(run)));
+        let tiles = route.tiles();
+        assert_eq!(route.heat_loss, tiles[1..].iter().map(|(y, x)| map[*y][*x] as u64).sum::<u64>());
+
+        assert_eq!(corner_to_corner(&map, Straight { min: 4, max: 10 }).unwrap().heat_loss, 94);
+        // an ultra crucible can't stop at the goal right after turning
+        let map = parse_map("111111111111\n999999999991\n999999999991\n999999999991\n999999999991").unwrap();
+        assert_eq!(corner_to_corner(&map, Straight { min: 4, max: 10 }).unwrap().heat_loss, 71);
+        assert!(corner_to_corner(&map, Straight { min: 20, max: 20 }).is_none());
+    }
+
+    #[test]
+    fn test_parse_coords() {
+        assert_eq!(parse_coords("start", "13,1").unwrap(), (12, 0));
+        assert!(parse_coords("start", "0,1").is_err());
+        assert!(parse_coords("goal", "1;1").is_err());
+    }
+}
@@ -1,3 +1,17 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use nom::bytes::complete::{tag, take_while_m_n};
+use nom::character::complete::{char, one_of, space1};
+use nom::combinator::{map_opt, map_res};
+use nom::sequence::{delimited, preceded, tuple};
+use nom::IResult;
+use crate::common::geometry::{Location, Polygon};
+use crate::common::parsing::{parse_lines, unsigned, ParseError};
+use crate::common::render::{frame_step, Color, Glyph, Render};
+use crate::solutions::Options;
+
 const EXAMPLE: &str = "R 6 (#70c710)
 D 5 (#0dc571)
 L 2 (#5713f0)
@@ -21,69 +35,251 @@ enum Direction {
     Right,
 }
 
+#[derive(Debug, Copy, Clone)]
 struct Instruction {
     direction: Direction,
     steps: i64,
+    /// Colour painted on the trench edge. Only known in part 1, part 2 reads the edge from the colour code.
+    color: Option<Color>,
+}
+
+/// A colour code like `#70c710`: the colour of the trench edge, and the instruction part 2 reads from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ColorCode {
+    hex: String,
+    color: Color,
+    /// The first five digits.
+    steps: i64,
+    /// The last digit, 0 to 3 for right, down, left and up.
+    direction: u8,
+}
+
+impl ColorCode {
+    fn direction(&self) -> Option<Direction> {
+        match self.direction {
+            0 => Some(Direction::Right),
+            1 => Some(Direction::Down),
+            2 => Some(Direction::Left),
+            3 => Some(Direction::Up),
+            _ => None,
+        }
+    }
 }
 
 type Coords = (i64, i64);
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+#[derive(Debug)]
+pub enum DigError {
+    Parse(ParseError),
+    /// The trench doesn't lead back to where digging started.
+    NotClosed { end: Coords },
+    /// The trench dug on `line` crosses or runs along the one dug on `other`.
+    SelfIntersecting { line: usize, other: usize },
+    ZeroArea,
+    /// The colour code on `line` doesn't end in a direction, which part 2 reads it for.
+    InvalidCode { line: usize, code: String },
+}
+
+impl Display for DigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DigError::Parse(e) => write!(f, "{}", e),
+            DigError::NotClosed { end: (y, x) } => {
+                write!(f, "the trench ends at row {}, column {} instead of back at row 0, column 0 where it started", y, x)
+            }
+            DigError::SelfIntersecting { line, other } => {
+                write!(f, "the trench dug on line {} runs into the one from line {}", line, other)
+            }
+            DigError::ZeroArea => write!(f, "the trench doesn't enclose any area"),
+            DigError::InvalidCode { line, code } => {
+                write!(f, "line {}: colour code {} doesn't end in a direction from 0 to 3", line, code)
+            }
+        }
+    }
+}
+
+impl Error for DigError {}
+
+impl From<ParseError> for DigError {
+    fn from(e: ParseError) -> DigError {
+        DigError::Parse(e)
+    }
+}
+
+/// Largest lagoon bounding box, in cells, that still gets drawn.
+const MAX_RENDERED_CELLS: i64 = 250_000;
+
+/// The trench dug so far, and the lagoon interior once the trench is closed.
+/// Edge `i` runs from `trench[i]` to `trench[i + 1]` and is painted `colors[i]`.
+struct TrenchView<'a> {
+    trench: &'a [Coords],
+    colors: &'a [Color],
+    lagoon: Option<&'a Polygon>,
+    bounds: (Coords, Coords),
+}
+
+impl Render for TrenchView<'_> {
+    fn glyphs(&self) -> Vec<Vec<Glyph>> {
+        let mut trench_cells = HashMap::new();
+        for ((from, to), color) in self.trench.iter().zip(self.trench.iter().skip(1)).zip(self.colors) {
+            let step = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+            let mut cell = *from;
+            trench_cells.insert(cell, *color);
+            while cell != *to {
+                cell = (cell.0 + step.0, cell.1 + step.1);
+                trench_cells.insert(cell, *color);
+            }
+        }
+
+        let ((min_y, min_x), (max_y, max_x)) = self.bounds;
+        (min_y..=max_y).map(|y| {
+            (min_x..=max_x).map(|x| {
+                if let Some(color) = trench_cells.get(&(y, x)) {
+                    Glyph::new('#', *color)
+                } else if self.lagoon.is_some_and(|l| l.locate((y, x)) == Location::Inside) {
+                    Glyph::new('#', Color::Blue)
+                } else {
+                    Glyph::new('.', Color::Gray)
+                }
+            }).collect()
+        }).collect()
+    }
+}
+
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, DigError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
-    let instructions = data.lines().map(|l| parse_line(l, advanced)).collect::<Vec<Instruction>>();
+    let (plain, codes): (Vec<_>, Vec<_>) = parse_lines(&data, 1, plan_line)?.into_iter().unzip();
+    if options.explain {
+        compare_plans(&plain, &codes);
+    }
+    let instructions = if advanced { hex_plan(&codes)? } else { plain };
     let vertices = find_vertices(&instructions);
-    let boundary = find_boundary(&instructions);
-    let area = shoelace(&vertices);
-    let result = picks(area, boundary) + boundary;
-    format!("{}", result)
+    let lagoon = dig(&vertices)?;
+    let result = lagoon.lattice_points();
+
+    if options.visualizer.is_some() || options.exporter.is_some() {
+        let colors = instructions.iter().map(|i| i.color.unwrap_or(Color::Yellow)).collect::<Vec<_>>();
+        draw_lagoon(&vertices, &colors, &lagoon, options);
+    }
+    Ok(format!("{}", result))
 }
 
-fn parse_line(line: &str, advanced: bool) -> Instruction {
-    let parts = line.split(" ").collect::<Vec<&str>>();
-    let (d, s, c) = match parts[..] {
-        [d, s, c] => (
-            match d { "U" => Direction::Up, "D" => Direction::Down, "L" => Direction::Left, "R" => Direction::Right, _ => panic!("Invalid direction: {}", d)},
-            s.parse::<i64>().unwrap(),
-            c.to_string(),
-        ),
-        _ => panic!("Invalid line: {}", line),
-    };
+/// Prints the trench and lagoon sizes for both readings of the plan next to each other.
+fn compare_plans(plain: &[Instruction], codes: &[ColorCode]) {
+    println!("{:<14}{:>8}{:>16}{:>18}", "Plan", "Edges", "Trench", "Lagoon");
+    for (name, instructions) in [("directions", Ok(plain.to_vec())), ("colour codes", hex_plan(codes))] {
+        match instructions.and_then(|i| Ok((i.len(), dig(&find_vertices(&i))?))) {
+            Ok((edges, lagoon)) => println!("{:<14}{:>8}{:>16}{:>18}", name, edges, lagoon.boundary_points(), lagoon.lattice_points()),
+            Err(e) => println!("{:<14}{}", name, e),
+        }
+    }
+}
 
-    if advanced {
-        let (d, s) = parse_hex(&c);
+/// The plan as part 2 reads it, from the colour code on every line.
+fn hex_plan(codes: &[ColorCode]) -> Result<Vec<Instruction>, DigError> {
+    codes.iter().enumerate().map(|(index, code)| {
+        let direction = code.direction().ok_or(DigError::InvalidCode { line: index + 1, code: code.hex.clone() })?;
+        Ok(Instruction { direction, steps: code.steps, color: None })
+    }).collect()
+}
 
-        Instruction { direction: d, steps: s }
+/// The lagoon enclosed by the trench through `vertices`, if the trench makes a proper loop.
+fn dig(vertices: &[Coords]) -> Result<Polygon, DigError> {
+    let end = *vertices.last().unwrap();
+    if end != vertices[0] {
+        return Err(DigError::NotClosed { end });
     }
-    else {
-        Instruction { direction: d, steps: s }
+    if let Some((line, other)) = find_intersection(vertices) {
+        return Err(DigError::SelfIntersecting { line, other });
     }
+    let lagoon = Polygon::new(vertices.to_vec());
+    if lagoon.doubled_area() == 0 {
+        return Err(DigError::ZeroArea);
+    }
+    Ok(lagoon)
 }
 
-fn parse_hex(hex: &str) -> (Direction, i64) {
-    let stripped_hex = hex
-        .strip_prefix("(").unwrap()
-        .strip_prefix("#").unwrap()
-        .strip_suffix(")").unwrap();
+/// Lines of the first two trench edges that touch anywhere but at the corner they share. Edges that are dug
+/// zero steps long are left out.
+fn find_intersection(vertices: &[Coords]) -> Option<(usize, usize)> {
+    let edges = vertices.windows(2)
+        .enumerate()
+        .filter(|(_, w)| w[0] != w[1])
+        .map(|(i, w)| (i + 1, w[0], w[1]))
+        .collect::<Vec<_>>();
+    let span = |a: i64, b: i64| (a.min(b), a.max(b));
+    let overlap = |(a0, a1): (i64, i64), (b0, b1): (i64, i64)| a0.max(b0) <= a1.min(b1);
+    for (i, &(line, from, to)) in edges.iter().enumerate() {
+        for (j, &(other, other_from, other_to)) in edges.iter().enumerate().take(i) {
+            let neighbours = j + 1 == i || (j == 0 && i == edges.len() - 1);
+            let touch = if neighbours {
+                // neighbours share a corner, so they only run into each other when the trench turns back
+                let direction = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+                let other_direction = ((other_to.0 - other_from.0).signum(), (other_to.1 - other_from.1).signum());
+                direction == (-other_direction.0, -other_direction.1)
+            } else {
+                overlap(span(from.0, to.0), span(other_from.0, other_to.0)) && overlap(span(from.1, to.1), span(other_from.1, other_to.1))
+            };
+            if touch {
+                return Some((line, other));
+            }
+        }
+    }
+    None
+}
 
+fn draw_lagoon(vertices: &[Coords], colors: &[Color], lagoon: &Polygon, options: &Options) {
+    let min = (vertices.iter().map(|v| v.0).min().unwrap(), vertices.iter().map(|v| v.1).min().unwrap());
+    let max = (vertices.iter().map(|v| v.0).max().unwrap(), vertices.iter().map(|v| v.1).max().unwrap());
+    let cells = (max.0 - min.0 + 1) * (max.1 - min.1 + 1);
+    if cells > MAX_RENDERED_CELLS {
+        println!("Lagoon is too large to draw ({} cells)", cells);
+        return;
+    }
 
-    let steps_str = &stripped_hex[..5];
-    let direction_str = &stripped_hex[5..];
-    let steps = i64::from_str_radix(steps_str, 16).unwrap();
-    let direction = match direction_str {
-        "0" => Direction::Right,
-        "1" => Direction::Down,
-        "2" => Direction::Left,
-        "3" => Direction::Up,
-        _ => panic!("Invalid direction: {}", direction_str),
-    };
-    (direction, steps)
+    let view = |end: usize, lagoon| TrenchView { trench: &vertices[..=end], colors, lagoon, bounds: (min, max) };
+    if let Some(visualizer) = &options.visualizer {
+        for end in (1..vertices.len()).step_by(frame_step(vertices.len())) {
+            visualizer.frame(&view(end, None));
+        }
+        visualizer.show(&view(vertices.len() - 1, Some(lagoon)));
+    }
+    if let Some(exporter) = &options.exporter {
+        for end in (1..vertices.len()).step_by(frame_step(vertices.len())) {
+            exporter.frame(&view(end, None));
+        }
+        exporter.export(&view(vertices.len() - 1, Some(lagoon)));
+    }
+}
+
+/// A line of the dig plan: the direction and distance it states, and its colour code.
+fn plan_line(input: &str) -> IResult<&str, (Instruction, ColorCode)> {
+    let direction = map_opt(one_of("UDLR"), |d| match d {
+        'U' => Some(Direction::Up),
+        'D' => Some(Direction::Down),
+        'L' => Some(Direction::Left),
+        'R' => Some(Direction::Right),
+        _ => None,
+    });
+    let (rest, (direction, steps, code)) = tuple((direction, preceded(space1, unsigned), preceded(space1, color_code)))(input)?;
+    let plain = Instruction { direction, steps, color: Some(code.color) };
+    Ok((rest, (plain, code)))
 }
 
-fn det(m: [[i64; 2]; 2]) -> i64 {
-    m[0][0] * m[1][1] - m[0][1] * m[1][0]
+fn color_code(input: &str) -> IResult<&str, ColorCode> {
+    // example: (#70c710)
+    let digits = take_while_m_n(6, 6, |c: char| c.is_ascii_hexdigit());
+    map_res(delimited(tag("(#"), digits, char(')')), |digits: &str| {
+        u32::from_str_radix(digits, 16).map(|value| ColorCode {
+            hex: format!("#{}", digits),
+            color: Color::Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8),
+            steps: (value >> 4) as i64,
+            direction: (value & 0xf) as u8,
+        })
+    })(input)
 }
 
-fn find_vertices(instructions: &Vec<Instruction>) -> Vec<Coords> {
+fn find_vertices(instructions: &[Instruction]) -> Vec<Coords> {
     let mut vertices: Vec<Coords> = Vec::from([(0, 0)]);
     let mut cursor: Coords = (0, 0);
     for instruction in instructions {
@@ -99,29 +295,44 @@ fn find_vertices(instructions: &Vec<Instruction>) -> Vec<Coords> {
     return vertices;
 }
 
-fn find_boundary(instructions: &Vec<Instruction>) -> i64 {
-    instructions.iter().map(|i| i.steps).sum()
-}
-
-fn shoelace(vertices: &Vec<Coords>) -> i64 {
-    let matrices = vertices.iter().zip(vertices.iter().skip(1)).map(|(v1, v2)| {
-        [[v1.0, v2.0], [v1.1, v2.1]]
-    });
-    let determinants = matrices.map(det);
-    let sum = determinants.sum::<i64>();
-    sum.abs() / 2
-}
-
-fn picks(area: i64, boundary: i64) -> i64 {
-    area - (boundary / 2) + 1
-}
-
 #[cfg(test)]
 mod tests {
+    use crate::common::parsing::parse_line;
     use super::*;
 
     #[test]
-    fn test_parse_hex() {
-        assert_eq!(parse_hex("(#70c710)"), (Direction::Right, 461937))
+    fn test_color_code() {
+        let code = parse_line(1, "(#70c710)", color_code).unwrap();
+        assert_eq!(code.color, Color::Rgb(0x70, 0xc7, 0x10));
+        assert_eq!((code.direction(), code.steps), (Some(Direction::Right), 461937));
+        assert_eq!(parse_line(1, "(#70c714)", color_code).unwrap().direction(), None);
+        assert!(parse_line(1, "(#70c71)", color_code).is_err());
+    }
+
+    fn dig_plan(plan: &str) -> Result<Polygon, DigError> {
+        let (plain, _): (Vec<_>, Vec<_>) = parse_lines(plan, 1, plan_line)?.into_iter().unzip();
+        dig(&find_vertices(&plain))
+    }
+
+    #[test]
+    fn test_dig() {
+        assert_eq!(dig_plan(EXAMPLE).unwrap().lattice_points(), 62);
+        assert!(matches!(dig_plan("R 2 (#000000)\nD 2 (#000000)\nL 2 (#000000)"), Err(DigError::NotClosed { end: (2, 0) })));
+        // a figure eight crossing itself in the middle
+        let eight = "R 2 (#000000)\nD 4 (#000000)\nR 2 (#000000)\nU 2 (#000000)\nL 4 (#000000)\nU 2 (#000000)";
+        assert!(matches!(dig_plan(eight), Err(DigError::SelfIntersecting { line: 5, other: 2 })));
+        assert!(matches!(dig_plan("R 3 (#000000)\nL 3 (#000000)"), Err(DigError::SelfIntersecting { line: 2, other: 1 })));
+        assert!(matches!(dig_plan("R 0 (#000000)"), Err(DigError::ZeroArea)));
+        assert!(matches!(dig_plan("R 2 (#000000)\nX 2 (#000000)"), Err(DigError::Parse(_))));
+    }
+
+    #[test]
+    fn test_colour_codes_only_matter_for_part_2() {
+        let square = "R 2 (#000007)\nD 2 (#000021)\nL 2 (#000022)\nU 2 (#000023)";
+        assert_eq!(solve(Some(square.to_string()), false, &Options::default()).unwrap(), "9");
+        assert!(matches!(
+            solve(Some(square.to_string()), true, &Options::default()),
+            Err(DigError::InvalidCode { line: 1, .. })
+        ));
     }
 }
@@ -1,13 +1,22 @@
-use std::{cmp, fmt};
-use std::collections::HashMap;
-use std::fmt::Formatter;
-use nom::bytes::complete::{is_not, is_a, take_while1};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use itertools::Itertools;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while1};
 use nom::AsChar;
-use nom::character::complete::{char, one_of};
-use nom::combinator::opt;
+use nom::character::complete::{char, space0};
+use nom::combinator::{opt, rest, value};
 use nom::IResult;
-use nom::multi::{many0, many1};
-use nom::sequence::{delimited};
+use nom::multi::{many0, separated_list1};
+use nom::sequence::{delimited, pair, preceded, separated_pair};
+use crate::common::dot::DotGraph;
+use crate::common::graph::strongly_connected_components;
+use crate::common::hyperrect::HyperRect;
+use crate::common::interval::{Interval, IntervalSet};
+use crate::common::parsing::{blocks, parse_line, parse_lines, signed, ParseError};
+use crate::solutions::{OptionError, Options};
 
 const EXAMPLE: &str = "px{a<2006:qkq,m>2090:A,rfg}
 pv{a>1716:R,A}
@@ -27,105 +36,92 @@ hdj{m>838:A,pv}
 {x=2461,m=1339,a=466,s=291}
 {x=2127,m=1623,a=2188,s=1013}";
 
-#[derive(Debug, Clone, Copy)]
-struct Item {
-    x: i64,
-    m: i64,
-    a: i64,
-    s: i64,
-}
-
+type Item = HashMap<String, i64>;
 
-#[derive(Clone, Copy)]
-struct ItemRange {
-    x: (i64, i64),
-    m: (i64, i64),
-    a: (i64, i64),
-    s: (i64, i64),
-}
-
-impl fmt::Debug for ItemRange {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{{x={:04}-{:04} m={:04}-{:04} a={:04}-{:04} s={:04}-{:04}}}", self.x.0, self.x.1, self.m.0, self.m.1, self.a.0, self.a.1, self.s.0, self.s.1)
-    }
+/// Attribute names in a fixed order, so that each of them gets its own axis in a `HyperRect`.
+#[derive(Debug)]
+struct Attributes {
+    names: Vec<String>,
+    /// Attributes that rules or constraints look at, which every part needs a value for.
+    tested: BTreeSet<String>,
 }
 
-impl ItemRange {
-    fn var_value(&self, variable: &str) -> (i64, i64) {
-        match variable {
-            "x" => self.x,
-            "m" => self.m,
-            "a" => self.a,
-            "s" => self.s,
-            _ => panic!("Invalid variable: {}", variable),
-        }
-    }
-
-    fn set_var_value(&mut self, variable: &str, value: (i64, i64)) {
-        match variable {
-            "x" => self.x = value,
-            "m" => self.m = value,
-            "a" => self.a = value,
-            "s" => self.s = value,
-            _ => panic!("Invalid variable: {}", variable),
+impl Attributes {
+    /// Every attribute that workflows, parts, `constrained` or `declared` domains name. A declared domain
+    /// counts as an axis even if nothing else names it.
+    fn collect<'a>(
+        workflows: &[Workflow],
+        items: impl Iterator<Item = &'a Item>,
+        constrained: impl Iterator<Item = &'a String>,
+        declared: impl Iterator<Item = &'a String>,
+    ) -> Attributes {
+        let rule_names = workflows
+            .iter()
+            .flat_map(|w| w.rules.iter())
+            .filter_map(|r| r.variable.clone());
+        let tested = rule_names.chain(constrained.cloned()).collect::<BTreeSet<_>>();
+        let item_names = items.flat_map(|i| i.keys().cloned());
+        Attributes {
+            names: tested.iter().cloned().chain(item_names).chain(declared.cloned()).collect::<BTreeSet<_>>().into_iter().collect(),
+            tested,
         }
     }
 
-    fn default() -> ItemRange {
-        ItemRange {
-            x: (1, 4000),
-            m: (1, 4000),
-            a: (1, 4000),
-            s: (1, 4000),
-        }
+    /// Box of all the values the attributes can take, with `domains` overriding the default domain.
+    fn domain(&self, domains: &HashMap<String, Interval>) -> HyperRect {
+        let default = Interval::inclusive(DEFAULT_DOMAIN.0, DEFAULT_DOMAIN.1);
+        HyperRect::new(self.names.iter().map(|n| domains.get(n).copied().unwrap_or(default)).collect())
     }
 
-    fn empty() -> ItemRange {
-        ItemRange {
-            x: (0, 0),
-            m: (0, 0),
-            a: (0, 0),
-            s: (0, 0),
+    fn axis(&self, name: &str) -> usize {
+        match self.names.iter().position(|n| n == name) {
+            Some(axis) => axis,
+            None => panic!("Invalid variable: {}", name),
         }
     }
 
-    fn is_empty(&self) -> bool {
-        self.x == (0, 0) && self.m == (0, 0) && self.a == (0, 0) && self.s == (0, 0)
+    /// The values of `item` in axis order, or the first tested attribute it lacks. Attributes that are never
+    /// tested don't decide anything, so the part gets 0 for those it lacks.
+    fn values(&self, item: &Item) -> Result<Vec<i64>, String> {
+        self.names.iter().map(|n| match item.get(n) {
+            Some(value) => Ok(*value),
+            None if self.tested.contains(n) => Err(n.clone()),
+            None => Ok(0),
+        }).collect()
     }
+}
 
-    fn split(&self, var_name: &str, operator: char, value: i64) -> Option<(ItemRange, ItemRange)> {
-        let original_range = self.var_value(var_name);
-
-        if value < original_range.0 || value > original_range.1 {
-            return None;
-        }
-
-        let new_lower_range = (original_range.0, cmp::min(original_range.1, value) - if operator == '<' { 1 } else { 0 });
-        let new_upper_range = (cmp::max(original_range.0, value) + if operator == '>' { 1 } else { 0 }, original_range.1);
-
-        let mut r1 = self.clone();
-        let mut r2 = self.clone();
-
-        r1.set_var_value(var_name, new_lower_range);
-        r2.set_var_value(var_name, new_upper_range);
-        // println!("Splitting: {} {} {} {} -> {} {} {} {}", original_range.0, original_range.1, operator, value, new_lower_range.0, new_lower_range.1, new_upper_range.0, new_upper_range.1);
-        return Some((r1, r2));
-    }
+/// Values every attribute can take, unless the input header or the command line say otherwise.
+const DEFAULT_DOMAIN: (i64, i64) = (1, 4000);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Operator {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
 
-    fn degrees_of_freedom(&self) -> i64 {
-        ["x", "m", "a", "s"]
-            .iter()
-            .map(|var| self.var_value(var))
-            .map(|(l, h)| h - l + 1)
-            .filter(|&diff| diff > 0)
-            .fold(1, |acc, diff| acc * diff)
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+        };
+        write!(f, "{}", symbol)
     }
 }
 
 #[derive(Debug)]
 struct Rule {
     variable: Option<String>,
-    operator: Option<core::primitive::char>,
+    operator: Option<Operator>,
     value: Option<i64>,
     workflow: String,
 }
@@ -134,49 +130,41 @@ impl Rule {
     fn is_default(&self) -> bool {
         self.variable.is_none() || self.operator.is_none() || self.value.is_none()
     }
-    fn apply(&self, item: &Item) -> Option<String> {
+
+    /// The condition as written in the input, e.g. `a<2006`. `None` for the default rule.
+    fn condition(&self) -> Option<String> {
         if self.is_default() {
-            return Some(self.workflow.clone());
+            return None;
         }
-        let rule_var = self.variable.as_ref().unwrap().as_str();
-        let item_value = match rule_var {
-            "x" => item.x,
-            "m" => item.m,
-            "a" => item.a,
-            "s" => item.s,
-            _ => panic!("Invalid variable: {}", rule_var),
-        };
+        Some(format!("{}{}{}", self.variable.as_ref().unwrap(), self.operator.unwrap(), self.value.unwrap()))
+    }
 
-        let rule_operator = self.operator.unwrap();
+    /// Values of the rule variable for which the rule matches. Intervals end before `i64::MAX`, so that value
+    /// never passes.
+    fn passing_values(&self) -> IntervalSet {
         let rule_value = self.value.unwrap();
-        match rule_operator {
-            '<' => if item_value < rule_value { return Some(self.workflow.clone()); },
-            '>' => if item_value > rule_value { return Some(self.workflow.clone()); },
-            _ => panic!("Invalid operator: {}", self.operator.unwrap()),
+        let after = rule_value.saturating_add(1);
+        match self.operator.unwrap() {
+            Operator::Less => IntervalSet::from(Interval::new(i64::MIN, rule_value)),
+            Operator::LessOrEqual => IntervalSet::from(Interval::new(i64::MIN, after)),
+            Operator::Greater => IntervalSet::from(Interval::new(after, i64::MAX)),
+            Operator::GreaterOrEqual => IntervalSet::from(Interval::new(rule_value, i64::MAX)),
+            Operator::Equal => IntervalSet::from(Interval::new(rule_value, after)),
+            Operator::NotEqual => IntervalSet::from_iter([
+                Interval::new(i64::MIN, rule_value),
+                Interval::new(after, i64::MAX),
+            ]),
         }
-        return None;
     }
 
-    fn apply_split(&self, item: &ItemRange) -> IResult<ItemRange, (String, ItemRange)> {
+    /// Splits `item` into the boxes matching this rule and the boxes falling through to the next one.
+    fn apply_split(&self, item: &HyperRect, attributes: &Attributes) -> (Vec<HyperRect>, Vec<HyperRect>) {
         if self.is_default() {
             // nothing fails by default
-            return Ok((ItemRange::empty(), (self.workflow.clone(), *item)));
+            return (vec![item.clone()], vec![]);
         }
-        let rule_var = self.variable.as_ref().unwrap().as_str();
-        let rule_value = self.value.unwrap();
-        let rule_operator = self.operator.unwrap();
-
-        let (r1, r2) = match item.split(rule_var, rule_operator, rule_value) {
-            Some((r1, r2)) => (r1, r2),
-            None => return Ok((*item, ("".to_string(), ItemRange::empty()))),
-        };
-
-        let (passing_range, failing_range) = match rule_operator {
-            '<' => (r1, r2),
-            '>' => (r2, r1),
-            _ => panic!("Invalid operator: {}", self.operator.unwrap()),
-        };
-        Ok((failing_range, (self.workflow.clone(), passing_range)))
+        let axis = attributes.axis(self.variable.as_ref().unwrap());
+        item.split_by(axis, &self.passing_values())
     }
 }
 
@@ -187,105 +175,382 @@ struct Workflow {
 }
 
 impl Workflow {
-    fn apply(&self, item: &Item) -> String {
-        for rule in &self.rules {
-            if let Some(workflow) = rule.apply(item) {
-                return workflow;
+    /// Numbers, counting from 1, of the rules that never send a part anywhere: the rules before them already
+    /// catch every part that would match them, whatever its values. Domains only limit part 2, so they don't
+    /// come into it.
+    fn unreachable_rules(&self, attributes: &Attributes) -> Vec<usize> {
+        let mut remaining = vec![HyperRect::uniform(attributes.names.len(), Interval::new(i64::MIN, i64::MAX))];
+        let mut unreachable = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let (passing, failing): (Vec<_>, Vec<_>) = remaining.iter().map(|r| rule.apply_split(r, attributes)).unzip();
+            if passing.iter().all(|p| p.is_empty()) {
+                unreachable.push(index + 1);
+            }
+            remaining = failing.into_iter().flatten().collect();
+        }
+        unreachable
+    }
+}
+
+#[derive(Debug)]
+pub enum WorkflowError {
+    Parse(ParseError),
+    Option(OptionError),
+    MissingStart,
+    DuplicateWorkflow(String),
+    UndefinedTarget { workflow: String, target: String },
+    /// Rule number `rule`, counting from 1, never sends a part anywhere.
+    UnreachableRule { workflow: String, rule: usize },
+    /// Workflows that can send a part back to themselves.
+    Cycle(Vec<String>),
+    MissingAttribute { line: usize, attribute: String },
+    TooManyCombinations,
+}
+
+impl Display for WorkflowError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkflowError::Parse(e) => write!(f, "{}", e),
+            WorkflowError::Option(e) => write!(f, "{}", e),
+            WorkflowError::MissingStart => write!(f, "there is no workflow called in"),
+            WorkflowError::DuplicateWorkflow(name) => write!(f, "workflow {} is defined more than once", name),
+            WorkflowError::UndefinedTarget { workflow, target } => {
+                write!(f, "workflow {} sends parts to {}, which is not defined", workflow, target)
             }
+            WorkflowError::UnreachableRule { workflow, rule } => {
+                write!(f, "no part ever makes it through rule {} of workflow {}", rule, workflow)
+            }
+            WorkflowError::Cycle(workflows) => write!(f, "workflows {} send parts around in circles", workflows.join(", ")),
+            WorkflowError::MissingAttribute { line, attribute } => write!(f, "line {}: the part has no {}", line, attribute),
+            WorkflowError::TooManyCombinations => write!(f, "there are too many accepted combinations to count"),
         }
-        panic!("No workflow found for item: {:?}", item);
     }
+}
+
+impl Error for WorkflowError {}
+
+impl From<ParseError> for WorkflowError {
+    fn from(e: ParseError) -> WorkflowError {
+        WorkflowError::Parse(e)
+    }
+}
+
+impl From<OptionError> for WorkflowError {
+    fn from(e: OptionError) -> WorkflowError {
+        WorkflowError::Option(e)
+    }
+}
+
+/// The rule a decision was compiled from, for explaining it.
+#[derive(Debug)]
+struct RuleRef {
+    workflow: String,
+    /// Position of the rule in its workflow, counting from 1.
+    number: usize,
+    /// The condition as written, `else` for the default rule.
+    condition: String,
+}
 
-    fn apply_split(&self, item: &ItemRange) -> Vec<(String, ItemRange)> {
-        let mut result = Vec::new();
-        let mut current_item = item.clone();
-        for rule in &self.rules {
-            let (failing_range, (workflow, passing_range)) = rule.apply_split(&current_item).unwrap();
-            if !passing_range.is_empty() {
-                result.push((workflow, passing_range));
+/// A step of the compiled workflows.
+#[derive(Debug)]
+enum Decision {
+    Accept,
+    Reject,
+    /// Conditional rule: parts with a value on `axis` in `passing` continue at `then`, the others at
+    /// `otherwise`.
+    Test { rule: RuleRef, axis: usize, passing: IntervalSet, then: usize, otherwise: usize },
+    /// Default rule, sending every part on to `then`.
+    Goto { rule: RuleRef, then: usize },
+}
+
+/// All workflows compiled into one tree of decisions, with workflow names resolved to node indices. Each rule
+/// is a node, and a conditional rule falls through to the next rule of its workflow.
+#[derive(Debug)]
+struct DecisionTree {
+    nodes: Vec<Decision>,
+    start: usize,
+}
+
+const ACCEPT: usize = 0;
+const REJECT: usize = 1;
+
+impl DecisionTree {
+    /// Validates the workflows, then compiles them. The targets are checked first, then whether every rule
+    /// can be reached, and finally that no part can be sent around in a cycle.
+    fn compile(workflows: &[Workflow], attributes: &Attributes) -> Result<DecisionTree, WorkflowError> {
+        let mut first_rule = HashMap::new();
+        let mut next_node = 2;
+        for workflow in workflows {
+            if first_rule.insert(workflow.name.as_str(), next_node).is_some() {
+                return Err(WorkflowError::DuplicateWorkflow(workflow.name.clone()));
+            }
+            next_node += workflow.rules.len();
+        }
+        let start = *first_rule.get("in").ok_or(WorkflowError::MissingStart)?;
+        let target = |workflow: &Workflow, rule: &Rule| match rule.workflow.as_str() {
+            "A" => Ok(ACCEPT),
+            "R" => Ok(REJECT),
+            name => first_rule.get(name).copied().ok_or_else(|| WorkflowError::UndefinedTarget {
+                workflow: workflow.name.clone(),
+                target: name.to_string(),
+            }),
+        };
+
+        let mut nodes = vec![Decision::Accept, Decision::Reject];
+        for workflow in workflows {
+            for (index, rule) in workflow.rules.iter().enumerate() {
+                let then = target(workflow, rule)?;
+                let rule_ref = RuleRef {
+                    workflow: workflow.name.clone(),
+                    number: index + 1,
+                    condition: rule.condition().unwrap_or_else(|| "else".to_string()),
+                };
+                nodes.push(match &rule.variable {
+                    Some(variable) if !rule.is_default() => Decision::Test {
+                        rule: rule_ref,
+                        axis: attributes.axis(variable),
+                        passing: rule.passing_values(),
+                        then,
+                        otherwise: nodes.len() + 1,
+                    },
+                    _ => Decision::Goto { rule: rule_ref, then },
+                });
+            }
+        }
+        for workflow in workflows {
+            if let Some(&rule) = workflow.unreachable_rules(attributes).first() {
+                return Err(WorkflowError::UnreachableRule { workflow: workflow.name.clone(), rule });
             }
-            current_item = failing_range;
         }
-        if !current_item.is_empty() {
-            println!("Workflow rules: {:?}", self.rules);
-            panic!("Failed to apply workflow: {:?}", item);
+
+        // a cycle between workflows is a strongly connected component with more than one workflow, or a
+        // workflow sending parts to itself
+        let index = workflows.iter().enumerate().map(|(i, w)| (w.name.as_str(), i)).collect::<HashMap<_, _>>();
+        let successors = workflows.iter()
+            .map(|w| w.rules.iter().filter_map(|r| index.get(r.workflow.as_str()).copied()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let (component, _) = strongly_connected_components(&successors);
+        for (i, next) in successors.iter().enumerate() {
+            let members = workflows.iter().enumerate().filter(|(j, _)| component[*j] == component[i]).collect::<Vec<_>>();
+            if members.len() > 1 || next.contains(&i) {
+                return Err(WorkflowError::Cycle(members.iter().map(|(_, w)| w.name.clone()).collect()));
+            }
         }
-        return result;
+        Ok(DecisionTree { nodes, start })
     }
 }
 
-pub fn solve(input_data: Option<String>, advanced: bool) -> String {
+pub fn solve(input_data: Option<String>, advanced: bool, options: &Options) -> Result<String, WorkflowError> {
     let data = input_data.unwrap_or(String::from(EXAMPLE)).trim().to_string();
-    let (wf_str, item_str) = data.split_once("\n\n").unwrap();
+    let input_blocks = blocks(&data);
+    let (header, (wf_line, wf_str), (item_line, item_str)) = match input_blocks[..] {
+        [workflows, items] => (None, workflows, items),
+        [header, workflows, items] => (Some(header), workflows, items),
+        _ => return Err(ParseError::new(1, "expected workflows and parts separated by a blank line").into()),
+    };
+    let mut domains = HashMap::new();
+    if let Some((header_line, header_str)) = header {
+        for (index, domain) in parse_lines(header_str, header_line, commented(parse_domain))?.into_iter().enumerate() {
+            if let Some((name, values)) = domain {
+                if values.is_empty() {
+                    return Err(ParseError::new(header_line + index, format!("the domain of {} is empty", name)).into());
+                }
+                domains.insert(name, values);
+            }
+        }
+    }
+    for domain in &options.domains {
+        let (name, values) = parse_line(1, domain.trim(), parse_domain)
+            .map_err(|e| OptionError::new("domain", domain, e.message))?;
+        if values.is_empty() {
+            return Err(OptionError::new("domain", domain, format!("the domain of {} is empty", name)).into());
+        }
+        domains.insert(name, values);
+    }
+    let constraints = options.constraints.iter()
+        .map(|c| parse_line(1, c.trim(), parse_condition).map_err(|e| OptionError::new("constraint", c, e.message)))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let workflow_map = wf_str
-        .lines()
-        .map(|l| parse_workflow(l).unwrap().1)
-        .map(|w| (w.name.clone(), w)).collect::<HashMap<_, _>>();
-    let items = item_str.lines().map(|l| parse_item(l).unwrap().1).collect::<Vec<_>>();
+    let workflows = parse_lines(wf_str, wf_line, commented(parse_workflow))?.into_iter().flatten().collect::<Vec<_>>();
+    if let Some(path) = &options.dot {
+        workflow_graph(&workflows).export(path);
+    }
+    let items = parse_lines(item_str, item_line, commented(parse_item))?
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, item)| item.map(|i| (item_line + index, i)))
+        .collect::<Vec<_>>();
+    let constraint_names = constraints.iter().filter_map(|c| c.variable.as_ref());
+    let attributes = Attributes::collect(&workflows, items.iter().map(|(_, i)| i), constraint_names, domains.keys());
+    let domain = attributes.domain(&domains);
+    let tree = DecisionTree::compile(&workflows, &attributes)?;
 
     let result = if advanced {
-        apply_with_ranges(&workflow_map, "in".to_string())
+        // only the combinations meeting every constraint count
+        let mut start = vec![domain];
+        for constraint in &constraints {
+            start = start.iter().flat_map(|r| constraint.apply_split(r, &attributes).0).collect();
+        }
+        let accepted = apply_with_ranges(&tree, start);
+        if options.explain {
+            explain_ranges(&attributes, &accepted);
+        }
+        let combinations = accepted.iter().try_fold(0u128, |total, r| total.checked_add(r.degrees_of_freedom()?))
+            .ok_or(WorkflowError::TooManyCombinations)?;
+        format!("{}", combinations)
     } else {
-        items
-            .into_iter()
-            .filter(|i| is_accepted(&workflow_map, i))
-            .map(|i| i.x + i.m + i.a + i.s)
-            .sum::<i64>() as u64
+        // ratings can be negative in a custom domain, and i128 holds any sum of them
+        let mut total = 0i128;
+        for (line, item) in &items {
+            let values = attributes.values(item)
+                .map_err(|attribute| WorkflowError::MissingAttribute { line: *line, attribute })?;
+            if options.explain {
+                let part = data.lines().nth(line - 1).unwrap_or_default().split('#').next().unwrap_or_default();
+                explain_part(&tree, part.trim(), &values);
+            }
+            let allowed = constraints.iter().all(|c| {
+                c.passing_values().contains(values[attributes.axis(c.variable.as_ref().unwrap())])
+            });
+            if allowed && is_accepted(&tree, &values) {
+                total += item.values().map(|v| *v as i128).sum::<i128>();
+            }
+        }
+        format!("{}", total)
     };
 
-    format!("{}", result)
+    Ok(result)
 }
 
-fn is_accepted(workflow_map: &HashMap<String, Workflow>, item: &Item) -> bool {
-    let default_workflow_name = "in".to_string();
-    let mut current_workflow_name = default_workflow_name;
-    while current_workflow_name != "R" && current_workflow_name != "A" {
-        let current_workflow = workflow_map.get(&current_workflow_name).unwrap();
-        current_workflow_name = current_workflow.apply(&item);
+/// Workflows with an edge per rule, labelled with its condition, ending in the accept and reject sinks.
+fn workflow_graph(workflows: &[Workflow]) -> DotGraph {
+    let mut graph = DotGraph::new();
+    graph.node("in", &[("style", "filled"), ("fillcolor", "lightblue")]);
+    graph.node("A", &[("shape", "doublecircle"), ("style", "filled"), ("fillcolor", "palegreen")]);
+    graph.node("R", &[("shape", "doublecircle"), ("style", "filled"), ("fillcolor", "salmon")]);
+    for workflow in workflows.iter().sorted_by_key(|w| &w.name) {
+        for rule in &workflow.rules {
+            let label = rule.condition().unwrap_or_else(|| "else".to_string());
+            graph.edge(&workflow.name, &rule.workflow, &[("label", &label)]);
+        }
     }
+    graph
+}
 
-    return match current_workflow_name.as_str() {
-        "A" => true,
-        "R" => false,
-        _ => panic!("Invalid workflow: {:?}", current_workflow_name),
-    };
+fn is_accepted(tree: &DecisionTree, values: &[i64]) -> bool {
+    fired_rules(tree, values).1
+}
+
+/// The rules that send the part on, in order, and whether the part ends up accepted.
+fn fired_rules<'a>(tree: &'a DecisionTree, values: &[i64]) -> (Vec<&'a RuleRef>, bool) {
+    let mut fired = Vec::new();
+    let mut node = tree.start;
+    loop {
+        node = match &tree.nodes[node] {
+            Decision::Accept => return (fired, true),
+            Decision::Reject => return (fired, false),
+            Decision::Test { rule, axis, passing, then, otherwise } => {
+                if passing.contains(values[*axis]) {
+                    fired.push(rule);
+                    *then
+                } else {
+                    *otherwise
+                }
+            }
+            Decision::Goto { rule, then } => {
+                fired.push(rule);
+                *then
+            }
+        };
+    }
 }
 
-fn apply_with_ranges(workflow_map: &HashMap<String, Workflow>, workflow_name: String) -> u64 {
+/// Disjoint boxes covering every accepted combination of attribute values out of the `start` boxes.
+fn apply_with_ranges(tree: &DecisionTree, start: Vec<HyperRect>) -> Vec<HyperRect> {
     let mut result = Vec::new();
-    let mut queue = vec![(workflow_name.clone(), ItemRange::default())];
-    while !queue.is_empty() {
-        let (workflow_name, item) = queue.pop().unwrap();
-        let workflow = workflow_map.get(&workflow_name).unwrap();
-        let new_items = workflow.apply_split(&item);
-
-        for (new_workflow_name, new_item) in new_items {
-            match new_workflow_name.as_str() {
-                "A" => result.push(new_item),
-                "R" => continue,
-                _ => queue.push((new_workflow_name, new_item)),
+    let mut queue = start.into_iter().map(|r| (tree.start, r)).collect::<Vec<_>>();
+    while let Some((node, item)) = queue.pop() {
+        match &tree.nodes[node] {
+            Decision::Accept => result.push(item),
+            Decision::Reject => {}
+            Decision::Test { axis, passing, then, otherwise, .. } => {
+                let (passed, failed) = item.split_by(*axis, passing);
+                queue.extend(passed.into_iter().map(|p| (*then, p)));
+                queue.extend(failed.into_iter().map(|f| (*otherwise, f)));
             }
+            Decision::Goto { then, .. } => queue.push((*then, item)),
+        }
+    }
+    result
+}
+
+/// Prints the workflows a part goes through, and the rule that sends it on from each of them.
+fn explain_part(tree: &DecisionTree, part: &str, values: &[i64]) {
+    let (fired, accepted) = fired_rules(tree, values);
+    let workflows = fired.iter().map(|r| r.workflow.as_str()).chain([if accepted { "A" } else { "R" }]);
+    println!("{}: {}", part, workflows.format(" → "));
+    for rule in fired {
+        println!("  {}: rule {}, {}", rule.workflow, rule.number, rule.condition);
+    }
+}
+
+/// Prints every accepted box with the number of combinations it holds.
+fn explain_ranges(attributes: &Attributes, accepted: &[HyperRect]) {
+    for rect in accepted {
+        let axes = attributes.names.iter().zip(rect.axes())
+            .map(|(n, a)| format!("{} {}..={}", n, a.start(), a.last()))
+            .join(", ");
+        match rect.degrees_of_freedom() {
+            Some(volume) => println!("{}: {}", axes, volume),
+            None => println!("{}: too many to count", axes),
         }
     }
+}
 
-    result.iter().map(|i| i.degrees_of_freedom()).sum::<i64>() as u64
+/// `parser` on a line that may also just be, or end in, a `#` comment.
+fn commented<'a, T>(parser: impl FnMut(&'a str) -> IResult<&'a str, T>) -> impl FnMut(&'a str) -> IResult<&'a str, Option<T>> {
+    let comment = value((), preceded(char('#'), rest));
+    delimited(space0, opt(parser), pair(space0, opt(comment)))
+}
+
+fn parse_operator(input: &str) -> IResult<&str, Operator> {
+    alt((
+        value(Operator::LessOrEqual, tag("<=")),
+        value(Operator::GreaterOrEqual, tag(">=")),
+        value(Operator::Equal, tag("==")),
+        value(Operator::NotEqual, tag("!=")),
+        value(Operator::Less, tag("<")),
+        value(Operator::Greater, tag(">")),
+    ))(input)
+}
+
+/// A condition on its own, as a rule sending the parts that meet it to `A`.
+fn parse_condition(input: &str) -> IResult<&str, Rule> {
+    // example: a<2006
+    let (input, variable) = take_while1(|c: char| c.is_ascii_lowercase())(input)?;
+    let (input, operator) = parse_operator(input)?;
+    let (input, value) = signed(input)?;
+    Ok((input, Rule {
+        variable: Some(variable.to_string()),
+        operator: Some(operator),
+        value: Some(value),
+        workflow: "A".to_string(),
+    }))
 }
 
 fn parse_rule(input: &str) -> IResult<&str, Rule> {
-    // example: a<2006:qkq,m>2090:A,rfg
-    let (input, variable) = is_a("xmas")(input)?;
-    let (input, operator) = is_a("<>")(input)?;
-    let (input, value) = is_a("0123456789")(input)?;
+    // example: a<2006:qkq,
+    let (input, rule) = parse_condition(input)?;
     let (input, _) = char(':')(input)?;
     let (input, workflow_name) = take_while1(AsChar::is_alpha)(input)?;
     let (input, _) = char(',')(input)?;
-    Ok((input, Rule {
-        variable: Some(variable.to_string()),
-        operator: Some(operator.chars().next().unwrap()),
-        value: Some(value.parse::<i64>().unwrap()),
-        workflow: workflow_name.to_string(),
-    }))
+    Ok((input, Rule { workflow: workflow_name.to_string(), ..rule }))
+}
+
+fn parse_domain(input: &str) -> IResult<&str, (String, Interval)> {
+    // example: x=1..=4000
+    let (input, variable) = take_while1(|c: char| c.is_ascii_lowercase())(input)?;
+    let (input, (first, last)) = preceded(char('='), separated_pair(signed, tag("..="), signed))(input)?;
+    Ok((input, (variable.to_string(), Interval::inclusive(first, last))))
 }
 
 fn parse_rule_default(input: &str) -> IResult<&str, Rule> {
@@ -302,9 +567,7 @@ fn parse_rule_default(input: &str) -> IResult<&str, Rule> {
 fn parse_workflow(input: &str) -> IResult<&str, Workflow> {
     // example: px{a<2006:qkq,m>2090:A,rfg}
     let (input, name) = take_while1(AsChar::is_alpha)(input)?;
-    let (_, input) = delimited(is_a("{"), is_not("}"), is_a("}"))(input)?;
-    let (input, rules) = many0(parse_rule)(input)?;
-    let (_, default_rule) = parse_rule_default(input)?;
+    let (input, (rules, default_rule)) = delimited(char('{'), pair(many0(parse_rule), parse_rule_default), char('}'))(input)?;
     let rules = rules.into_iter().chain(std::iter::once(default_rule)).collect::<Vec<_>>();
 
     Ok((input, Workflow {
@@ -313,31 +576,18 @@ fn parse_workflow(input: &str) -> IResult<&str, Workflow> {
     }))
 }
 
-fn parse_var(input: &str) -> IResult<&str, (char, i64)> {
+fn parse_var(input: &str) -> IResult<&str, (String, i64)> {
     // example: x=787
-    let (input, variable) = one_of("xmas")(input)?;
+    let (input, variable) = take_while1(|c: char| c.is_ascii_lowercase())(input)?;
     let (input, _) = char('=')(input)?;
-    let (input, value) = take_while1(AsChar::is_dec_digit)(input)?;
-    let (input, _) = opt(char(','))(input)?;
-    Ok((input, (variable, value.parse::<i64>().unwrap())))
+    let (input, value) = signed(input)?;
+    Ok((input, (variable.to_string(), value)))
 }
 
 fn parse_item(input: &str) -> IResult<&str, Item> {
     // example: {x=787,m=2655,a=1222,s=2876}
-    let (_, input, ) = delimited(is_a("{"), is_not("}"), is_a("}"))(input)?;
-    let (input, variables) = many1(parse_var)(input)?;
-
-    let mut item = Item { x: 0, m: 0, a: 0, s: 0 };
-    for (name, value) in variables {
-        match name {
-            'x' => item.x = value,
-            'm' => item.m = value,
-            'a' => item.a = value,
-            's' => item.s = value,
-            _ => panic!("Invalid variable: {}", name)
-        }
-    }
-    return Ok((input, item));
+    let (input, variables) = delimited(char('{'), separated_list1(char(','), parse_var), char('}'))(input)?;
+    Ok((input, variables.into_iter().collect()))
 }
 
 #[cfg(test)]
@@ -351,7 +601,7 @@ mod tests {
         assert_eq!(workflow.name, "hlb");
         assert_eq!(workflow.rules.len(), 3);
         assert_eq!(workflow.rules[0].variable, Some("x".to_string()));
-        assert_eq!(workflow.rules[0].operator, Some('>'));
+        assert_eq!(workflow.rules[0].operator, Some(Operator::Greater));
         assert_eq!(workflow.rules[0].value, Some(1866));
         assert_eq!(workflow.rules[2].workflow, "xdx");
     }
@@ -360,27 +610,178 @@ mod tests {
     fn test_rule_apply_split() {
         let mut rule = Rule {
             variable: Some("x".to_string()),
-            operator: Some('>'),
+            operator: Some(Operator::Greater),
             value: Some(1866),
             workflow: "xdx".to_string(),
         };
 
-        let range_item = ItemRange {
-            x: (1, 4000),
-            m: (1, 4000),
-            a: (1, 4000),
-            s: (1, 4000),
+        let attributes = Attributes {
+            names: ["x", "m", "a", "s"].iter().map(|n| n.to_string()).collect(),
+            tested: BTreeSet::new(),
         };
+        let range_item = attributes.domain(&HashMap::new());
 
-        let (rest, (wf, result)) = rule.apply_split(&range_item).expect("Should apply");
-        assert_eq!(wf, rule.workflow);
-        assert_eq!(result.x, (1867, 4000));
-        assert_eq!(rest.x, (1, 1866));
+        let (result, rest) = rule.apply_split(&range_item, &attributes);
+        assert_eq!(result, vec![range_item.with_axis(0, Interval::inclusive(1867, 4000))]);
+        assert_eq!(rest, vec![range_item.with_axis(0, Interval::inclusive(1, 1866))]);
 
-        rule.operator = Some('<');
-        let (rest, (wf, result)) = rule.apply_split(&range_item).expect("Should apply");
-        assert_eq!(wf, rule.workflow);
-        assert_eq!(result.x, (1, 1865));
-        assert_eq!(rest.x, (1866, 4000));
+        assert_eq!(rule.condition(), Some("x>1866".to_string()));
+
+        rule.operator = Some(Operator::Less);
+        let (result, rest) = rule.apply_split(&range_item, &attributes);
+        assert_eq!(result, vec![range_item.with_axis(0, Interval::inclusive(1, 1865))]);
+        assert_eq!(rest, vec![range_item.with_axis(0, Interval::inclusive(1866, 4000))]);
+
+        // rules at the edges of i64 don't overflow
+        rule.value = Some(i64::MAX);
+        for operator in [Operator::LessOrEqual, Operator::Greater, Operator::Equal, Operator::NotEqual] {
+            rule.operator = Some(operator);
+            assert!(!rule.passing_values().contains(i64::MAX));
+            assert_eq!(rule.passing_values().contains(0), operator != Operator::Greater && operator != Operator::Equal);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_any_attribute_names() {
+        let data = "in{speed>10:A,weight<5:A,R}\n\n{speed=11,weight=9}\n{speed=1,weight=9}\n{speed=1,weight=1}";
+        assert_eq!(solve(Some(data.to_string()), false, &Options::default()).unwrap(), "22");
+        // 3990 * 4000 with speed > 10, plus 10 * 4 with speed <= 10 and weight < 5
+        assert_eq!(solve(Some(data.to_string()), true, &Options::default()).unwrap(), format!("{}", 3990 * 4000 + 10 * 4));
+    }
+
+    #[test]
+    fn test_negative_thresholds() {
+        let data = "x=-10..=10\n\nin{x>-5:A,R}\n\n{x=-3}\n{x=-7}\n{x=2}";
+        let constrained = Options { constraints: vec!["x<-1".to_string()], ..Options::default() };
+        assert_eq!(solve(Some(data.to_string()), false, &Options::default()).unwrap(), "-1");
+        assert_eq!(solve(Some(data.to_string()), false, &constrained).unwrap(), "-3");
+        // x from -4 to -2
+        assert_eq!(solve(Some(data.to_string()), true, &constrained).unwrap(), "3");
+        let ratings = "x=-10..=10\n\nin{x<0:A,R}\n\n{x=-3}\n{x=-4}\n{x=2}";
+        assert_eq!(solve(Some(ratings.to_string()), false, &Options::default()).unwrap(), "-7");
+    }
+
+    #[test]
+    fn test_rule_language() {
+        let data = "# parts go through the rules in order
+in{x>=10:big,x!=5:R,A} # only x=5 gets through
+big{x==4000:A,x<=20:R,A}
+
+{x=5}
+{x=10}
+{x=4000}
+{x=7}";
+        assert_eq!(solve(Some(data.to_string()), false, &Options::default()).unwrap(), "4005");
+        // x=5, x=4000 and everything from 21 to 3999
+        assert_eq!(solve(Some(data.to_string()), true, &Options::default()).unwrap(), "3981");
+    }
+
+    #[test]
+    fn test_validation() {
+        let check = |workflows: &str| solve(Some(format!("{}\n\n{{x=1}}", workflows)), false, &Options::default());
+        assert!(check("in{x>1:A,R}\nother{A}").is_ok());
+        assert!(matches!(check("start{A}"), Err(WorkflowError::MissingStart)));
+        assert!(matches!(check("in{A}\nin{R}"), Err(WorkflowError::DuplicateWorkflow(_))));
+        assert!(matches!(check("in{x>1:qq,R}"), Err(WorkflowError::UndefinedTarget { .. })));
+        assert!(matches!(
+            check("in{x<10:A,x<5:R,A}"),
+            Err(WorkflowError::UnreachableRule { rule: 2, .. })
+        ));
+        assert!(matches!(check("in{x>=1:A,x<1:A,R}"), Err(WorkflowError::UnreachableRule { rule: 3, .. })));
+        // rules only count as unreachable if earlier rules catch every part, not just those in the domain
+        assert!(check("in{x>=1:A,R}").is_ok());
+        assert_eq!(solve(Some("in{x>4000:A,R}\n\n{x=5000}".to_string()), false, &Options::default()).unwrap(), "5000");
+        match check("in{x<10:a,R}\na{b}\nb{x>5:a,A}") {
+            Err(WorkflowError::Cycle(workflows)) => assert_eq!(workflows, vec!["a", "b"]),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        assert!(matches!(check("in{x<10:in,R}"), Err(WorkflowError::Cycle(_))));
+        assert!(matches!(
+            solve(Some("in{y<10:A,R}\n\n{y=1}\n{x=1}".to_string()), false, &Options::default()),
+            Err(WorkflowError::MissingAttribute { line: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_fired_rules() {
+        let (workflows, _) = EXAMPLE.split_once("\n\n").unwrap();
+        let workflows = parse_lines(workflows, 1, parse_workflow).unwrap();
+        let names = ["a", "m", "s", "x"].iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let attributes = Attributes { tested: names.iter().cloned().collect(), names };
+        let tree = DecisionTree::compile(&workflows, &attributes).unwrap();
+
+        let (fired, accepted) = fired_rules(&tree, &[1222, 2655, 2876, 787]);
+        assert!(accepted);
+        assert_eq!(fired.iter().map(|r| r.workflow.as_str()).collect::<Vec<_>>(), vec!["in", "qqz", "qs", "lnx"]);
+        assert_eq!((fired[1].number, fired[1].condition.as_str()), (1, "s>2770"));
+        assert_eq!(fired[2].condition, "else");
+
+        let accepted = apply_with_ranges(&tree, vec![attributes.domain(&HashMap::new())]);
+        assert_eq!(accepted.iter().map(|r| r.degrees_of_freedom().unwrap()).sum::<u128>(), 167409079868000);
+        assert!(accepted.iter().all(|r| is_accepted(&tree, &r.axes().iter().map(|a| a.start()).collect::<Vec<_>>())));
+    }
+
+    fn solve_with(data: &str, domains: &[&str], constraints: &[&str]) -> Result<String, WorkflowError> {
+        let options = Options {
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            constraints: constraints.iter().map(|c| c.to_string()).collect(),
+            ..Options::default()
+        };
+        solve(Some(data.to_string()), true, &options)
+    }
+
+    const SMALL: &str = "in{x<10:A,R}\n\n{x=1,y=1}";
+
+    #[test]
+    fn test_domain_precedence() {
+        assert_eq!(solve_with(SMALL, &[], &[]).unwrap(), format!("{}", 9 * 4000));
+        assert_eq!(solve_with(SMALL, &["y=0..=9"], &[]).unwrap(), "90");
+        // the header sets the domain, the command line overrides it
+        let with_header = format!("y=1..=2 # only two\nx=-5..=20\n\n{}", SMALL);
+        assert_eq!(solve_with(&with_header, &[], &[]).unwrap(), "30");
+        assert_eq!(solve_with(&with_header, &["y=1..=1"], &[]).unwrap(), "15");
+        // a declared domain is an axis of its own, even if nothing else names it
+        assert_eq!(solve_with(SMALL, &["q=1..=5"], &[]).unwrap(), format!("{}", 9 * 4000 * 5));
+        assert_eq!(solve_with(&format!("q=1..=3\n\n{}", SMALL), &[], &[]).unwrap(), format!("{}", 9 * 4000 * 3));
+    }
+
+    #[test]
+    fn test_constraints() {
+        assert_eq!(solve_with(SMALL, &["y=0..=9"], &["x>=5", "y!=3"]).unwrap(), "45");
+        let constrained = Options { constraints: vec!["q<3".to_string()], ..Options::default() };
+        assert!(matches!(
+            solve(Some(SMALL.to_string()), false, &constrained),
+            Err(WorkflowError::MissingAttribute { attribute, .. }) if attribute == "q"
+        ));
+    }
+
+    #[test]
+    fn test_required_attributes() {
+        // parts only need the attributes that get tested
+        assert_eq!(solve(Some("in{x<10:A,R}\n\n{x=1,y=2}\n{x=3}".to_string()), false, &Options::default()).unwrap(), "6");
+        assert_eq!(solve(Some(format!("q=1..=5\n\n{}", SMALL)), false, &Options::default()).unwrap(), "2");
+    }
+
+    #[test]
+    fn test_narrowed_domain() {
+        // narrowing x leaves the x>2440 rule of rfg out of reach, which is fine
+        assert_eq!(solve_with(EXAMPLE, &["x=1..=100"], &[]).unwrap(), "4655125256000");
+    }
+
+    #[test]
+    fn test_option_errors() {
+        assert!(matches!(solve_with(SMALL, &["y=5..=1"], &[]), Err(WorkflowError::Option(e)) if e.flag == "domain"));
+        assert!(matches!(solve_with(SMALL, &[], &["y"]), Err(WorkflowError::Option(e)) if e.flag == "constraint"));
+        assert!(matches!(solve_with(&format!("x=1..=2\ny=2..=1\n\n{}", SMALL), &[], &[]), Err(WorkflowError::Parse(e)) if e.line == 2));
+    }
+
+    #[test]
+    fn test_combination_overflow() {
+        // 3 attributes of 2^62 values each are more than fit in 128 bits
+        let big = "in{x<10:R,A}\n\n{x=1,y=1,z=1}";
+        let domains = ["x=0..=4611686018427387903", "y=0..=4611686018427387903", "z=0..=4611686018427387903"];
+        assert!(matches!(solve_with(big, &domains, &[]), Err(WorkflowError::TooManyCombinations)));
+        let count = (1u128 << 62) * (1 << 62) - 10 * (1 << 62);
+        assert_eq!(solve_with(big, &domains[..2], &["z==7"]).unwrap(), format!("{}", count));
+    }
+}
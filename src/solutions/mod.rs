@@ -1,3 +1,10 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::PathBuf;
+use crate::common::image::ImageExporter;
+use crate::common::render::Visualizer;
+
 mod day01;
 mod day02;
 mod day03;
@@ -18,27 +25,89 @@ mod day17;
 mod day18;
 mod day19;
 
-pub fn solve_for_day(day: u8, data: Option<String>, advanced: bool) -> String {
+pub use day16::{analyze_beam, parse_grid as parse_contraption, BeamReport, Direction as BeamDirection};
+
+/// Command line settings that only some of the days make use of.
+///
+/// Image and graph exports only warn when they fail, so a failed export never hides the puzzle answer.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub visualizer: Option<Visualizer>,
+    pub exporter: Option<ImageExporter>,
+    /// Where to write the Graphviz graph of the puzzle input (days 8 and 19).
+    pub dot: Option<PathBuf>,
+    /// Print how the answer was derived (days 5, 10, 13, 16, 17, 18 and 19).
+    pub explain: bool,
+    /// How many copies of each row to join for day 12, instead of 1 or 5.
+    pub fold: Option<usize>,
+    /// How many concrete arrangements to list for every day 12 row.
+    pub arrangements: Option<usize>,
+    /// Print a uniformly random arrangement for every day 12 row.
+    pub sample: bool,
+    /// How many smudges every day 13 block has, instead of 0 or 1.
+    pub smudges: Option<usize>,
+    /// Tilt program for day 14, e.g. `NWSE*1000000000` or `N,E,N`.
+    pub tilt: Option<String>,
+    /// Edge of the day 14 platform to measure the load on.
+    pub load_edge: Option<char>,
+    /// Fewest blocks the day 17 crucible moves in a row before it can turn or stop.
+    pub min_straight: Option<usize>,
+    /// Most blocks the day 17 crucible moves in a row before it has to turn.
+    pub max_straight: Option<usize>,
+    /// Tile the day 17 crucible starts on, as `ROW,COLUMN` counting from 1.
+    pub start: Option<String>,
+    /// Tile the day 17 crucible has to reach, as `ROW,COLUMN` counting from 1.
+    pub goal: Option<String>,
+    /// Values day 19 attributes can take, as `NAME=FIRST..=LAST`, instead of 1 to 4000.
+    pub domains: Vec<String>,
+    /// Conditions like `x<1000` that day 19 parts have to meet to be counted.
+    pub constraints: Vec<String>,
+}
+
+/// Command line option with a value the day can't work with, e.g. `--load-edge X`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionError {
+    /// Name of the flag, without the leading dashes.
+    pub flag: &'static str,
+    pub value: String,
+    pub message: String,
+}
+
+impl OptionError {
+    pub fn new(flag: &'static str, value: impl fmt::Display, message: impl Into<String>) -> OptionError {
+        OptionError { flag, value: value.to_string(), message: message.into() }
+    }
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid value {:?} for --{}: {}", self.value, self.flag, self.message)
+    }
+}
+
+impl Error for OptionError {}
+
+pub fn solve_for_day(day: u8, data: Option<String>, advanced: bool, options: &Options) -> Result<String, Box<dyn Error>> {
     match day {
-        1 => day01::solve(data, advanced),
-        2 => day02::solve(data, advanced),
-        3 => day03::solve(data, advanced),
-        4 => day04::solve(data, advanced),
-        5 => day05::solve(data, advanced),
-        6 => day06::solve(data, advanced),
-        7 => day07::solve(data, advanced),
-        8 => day08::solve(data, advanced),
-        9 => day09::solve(data, advanced),
-        10 => day10::solve(data, advanced),
-        11 => day11::solve(data, advanced),
-        12 => day12::solve(data, advanced),
-        13 => day13::solve(data, advanced),
-        14 => day14::solve(data, advanced),
-        15 => day15::solve(data, advanced),
-        16 => day16::solve(data, advanced),
-        17 => day17::solve(data, advanced),
-        18 => day18::solve(data, advanced),
-        19 => day19::solve(data, advanced),
-        _ => "Unknown day".to_string(),
+        1 => Ok(day01::solve(data, advanced)),
+        2 => Ok(day02::solve(data, advanced)?),
+        3 => Ok(day03::solve(data, advanced)),
+        4 => Ok(day04::solve(data, advanced)?),
+        5 => Ok(day05::solve(data, advanced, options)?),
+        6 => Ok(day06::solve(data, advanced)?),
+        7 => Ok(day07::solve(data, advanced)),
+        8 => Ok(day08::solve(data, advanced, options)?),
+        9 => Ok(day09::solve(data, advanced)?),
+        10 => Ok(day10::solve(data, advanced, options)?),
+        11 => Ok(day11::solve(data, advanced)),
+        12 => Ok(day12::solve(data, advanced, options)?),
+        13 => Ok(day13::solve(data, advanced, options)?),
+        14 => Ok(day14::solve(data, advanced, options)?),
+        15 => Ok(day15::solve(data, advanced)),
+        16 => Ok(day16::solve(data, advanced, options)),
+        17 => Ok(day17::solve(data, advanced, options)?),
+        18 => Ok(day18::solve(data, advanced, options)?),
+        19 => Ok(day19::solve(data, advanced, options)?),
+        _ => Err(format!("Unknown day: {}", day).into()),
     }
-}
\ No newline at end of file
+}